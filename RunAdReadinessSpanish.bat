@@ -0,0 +1,2 @@
+@echo off
+.\winnowdemo.exe AdReadiness.txt --locale es
\ No newline at end of file
@@ -13,7 +13,12 @@ Copy-Item README.md -Destination "$pkg"
 # Copy test data
 Copy-Item input.txt -Destination "$pkg"
 Copy-Item AdReadiness.txt -Destination "$pkg"
+Copy-Item AdReadiness.en.messages -Destination "$pkg"
+Copy-Item AdReadiness.es.messages -Destination "$pkg"
+Copy-Item input.test.txt -Destination "$pkg"
+Copy-Item AdReadiness.test.txt -Destination "$pkg"
 # Copy AdReadiness launcher
 Copy-Item RunAdReadiness.bat -Destination "$pkg"
+Copy-Item RunAdReadinessSpanish.bat -Destination "$pkg"
 # Compress
 Compress-Archive -Force -Path "$pkg" -DestinationPath "$pkg.zip"
\ No newline at end of file
@@ -0,0 +1,680 @@
+//! # analysis
+//! Treats a flow as a graph, with an edge for every way execution can leave a node:
+//! each branch option, a question's answered and exhausted destinations, score bands, split variants and timeouts.
+//!
+//! [`simple_paths`](fn.simple_paths.html) lists every way through a flow,
+//! [`cycles`](fn.cycles.html) finds the loops, and [`Coverage`](struct.Coverage.html)
+//! tallies which nodes and edges recorded sessions have actually used.
+//! [`possibly_unset`](fn.possibly_unset.html) finds prompts that can show a variable before anything sets it.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt,
+};
+
+use crate::{
+    condition::{guarded_texts, Condition},
+    required_variables,
+    transcript::{Cause, Entry, Event},
+    NodeId, NodeType, Nodes, TimeoutAction,
+};
+
+/// How execution leaves a node
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum EdgeKind {
+    /// Branch option, counting from 1 as shown to the user
+    Option(usize),
+    /// Question answered
+    Answered,
+    /// Question left blank at every prompt, or every branch option hidden by its guard
+    Exhausted,
+    /// Prompt timed out
+    Timeout,
+    /// Score band of a routing node, counting from 1
+    Band(usize),
+    /// Variant of a split node, counting from 1
+    Variant(usize),
+    /// Webhook call or command succeeded
+    Succeeded,
+    /// Webhook call or command failed
+    Failed,
+}
+
+/// A possible transition
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Edge {
+    /// Source node
+    pub from: NodeId,
+    /// Which way out of the source node
+    pub kind: EdgeKind,
+    /// Destination node
+    pub to: NodeId,
+}
+
+impl fmt::Display for Edge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            EdgeKind::Option(n) => format!("option {}", n),
+            EdgeKind::Answered => "answered".into(),
+            EdgeKind::Exhausted => "exhausted".into(),
+            EdgeKind::Timeout => "timeout".into(),
+            EdgeKind::Band(n) => format!("band {}", n),
+            EdgeKind::Variant(n) => format!("variant {}", n),
+            EdgeKind::Succeeded => "succeeded".into(),
+            EdgeKind::Failed => "failed".into(),
+        };
+        write!(f, "{} -> {} ({})", self.from, self.to, kind)
+    }
+}
+
+/// Every edge out of a node, in the order the node lists them
+pub fn edges_from(nodes: &Nodes, from: NodeId) -> Vec<Edge> {
+    let node = &nodes.flow.nodes[from];
+    let mut ret = Vec::new();
+    match &node.node_type {
+        NodeType::Branching(_, options) => {
+            for (i, option) in options.iter().enumerate() {
+                ret.push(Edge {
+                    from,
+                    kind: EdgeKind::Option(i + 1),
+                    to: option.destination,
+                });
+            }
+            if let Some(to) = node.exhausted {
+                ret.push(Edge {
+                    from,
+                    kind: EdgeKind::Exhausted,
+                    to,
+                });
+            }
+        }
+        NodeType::Question(success, fail, _) => {
+            ret.push(Edge {
+                from,
+                kind: EdgeKind::Answered,
+                to: *success,
+            });
+            ret.push(Edge {
+                from,
+                kind: EdgeKind::Exhausted,
+                to: *fail,
+            });
+        }
+        // Routing never waits, so never times out
+        NodeType::Routing(bands) => {
+            for (i, band) in bands.iter().enumerate() {
+                ret.push(Edge {
+                    from,
+                    kind: EdgeKind::Band(i + 1),
+                    to: band.destination,
+                });
+            }
+            return ret;
+        }
+        // Nor does a split
+        NodeType::Split(variants) => {
+            for (i, variant) in variants.iter().enumerate() {
+                ret.push(Edge {
+                    from,
+                    kind: EdgeKind::Variant(i + 1),
+                    to: variant.destination,
+                });
+            }
+            return ret;
+        }
+        // A webhook gives up on its own timeout
+        NodeType::Webhook(webhook) => {
+            ret.push(Edge {
+                from,
+                kind: EdgeKind::Succeeded,
+                to: webhook.success,
+            });
+            ret.push(Edge {
+                from,
+                kind: EdgeKind::Failed,
+                to: webhook.failure,
+            });
+            return ret;
+        }
+        NodeType::Command(command) => {
+            ret.push(Edge {
+                from,
+                kind: EdgeKind::Succeeded,
+                to: command.success,
+            });
+            ret.push(Edge {
+                from,
+                kind: EdgeKind::Failed,
+                to: command.failure,
+            });
+            return ret;
+        }
+        // Exit messages only lead out of the machine
+        NodeType::Terminating(_) => return ret,
+    }
+    if let Some(TimeoutAction::Goto(to)) = node.timeout.or(nodes.timeout).map(|t| t.action) {
+        ret.push(Edge {
+            from,
+            kind: EdgeKind::Timeout,
+            to,
+        });
+    }
+    ret
+}
+
+/// Every edge in the flow
+pub fn edges(nodes: &Nodes) -> Vec<Edge> {
+    (0..nodes.flow.nodes.len())
+        .flat_map(|n| edges_from(nodes, n))
+        .collect()
+}
+
+/// Whether a node ends execution
+pub fn is_terminating(nodes: &Nodes, node: NodeId) -> bool {
+    matches!(
+        nodes.flow.nodes.get(node).map(|n| &n.node_type),
+        Some(NodeType::Terminating(_))
+    )
+}
+
+/// Distinct destinations of a node, skipping any that don't exist
+fn successors(nodes: &Nodes, from: NodeId) -> Vec<NodeId> {
+    let mut ret: Vec<NodeId> = edges_from(nodes, from)
+        .into_iter()
+        .map(|e| e.to)
+        .filter(|to| *to < nodes.flow.nodes.len())
+        .collect();
+    ret.sort_unstable();
+    ret.dedup();
+    ret
+}
+
+/// Every path from `start` to a terminating node that visits no node twice,
+/// grouped by the terminating node reached
+pub fn simple_paths(nodes: &Nodes, start: NodeId) -> BTreeMap<NodeId, Vec<Vec<NodeId>>> {
+    /// Depth-first walk, extending `path`
+    fn walk(
+        nodes: &Nodes,
+        path: &mut Vec<NodeId>,
+        on_path: &mut Vec<bool>,
+        ret: &mut BTreeMap<NodeId, Vec<Vec<NodeId>>>,
+    ) {
+        let here = *path.last().unwrap();
+        if is_terminating(nodes, here) {
+            ret.entry(here).or_default().push(path.clone());
+            return;
+        }
+        for next in successors(nodes, here) {
+            if !on_path[next] {
+                on_path[next] = true;
+                path.push(next);
+                walk(nodes, path, on_path, ret);
+                path.pop();
+                on_path[next] = false;
+            }
+        }
+    }
+    let mut ret = BTreeMap::new();
+    if start >= nodes.flow.nodes.len() {
+        return ret;
+    }
+    let mut on_path = vec![false; nodes.flow.nodes.len()];
+    on_path[start] = true;
+    walk(nodes, &mut vec![start], &mut on_path, &mut ret);
+    ret
+}
+
+/// Every elementary cycle, each listed once starting from its lowest node
+pub fn cycles(nodes: &Nodes) -> Vec<Vec<NodeId>> {
+    /// Depth-first walk over nodes above `start`, closing a cycle on reaching `start` again
+    fn walk(
+        nodes: &Nodes,
+        start: NodeId,
+        path: &mut Vec<NodeId>,
+        on_path: &mut Vec<bool>,
+        ret: &mut Vec<Vec<NodeId>>,
+    ) {
+        let here = *path.last().unwrap();
+        for next in successors(nodes, here) {
+            if next == start {
+                ret.push(path.clone());
+            } else if next > start && !on_path[next] {
+                on_path[next] = true;
+                path.push(next);
+                walk(nodes, start, path, on_path, ret);
+                path.pop();
+                on_path[next] = false;
+            }
+        }
+    }
+    let mut ret = Vec::new();
+    let mut on_path = vec![false; nodes.flow.nodes.len()];
+    for start in 0..nodes.flow.nodes.len() {
+        on_path[start] = true;
+        walk(nodes, start, &mut vec![start], &mut on_path, &mut ret);
+        on_path[start] = false;
+    }
+    ret
+}
+
+/// Nodes reachable from `start`, including itself
+pub fn reachable(nodes: &Nodes, start: NodeId) -> BTreeSet<NodeId> {
+    let mut ret = BTreeSet::new();
+    if start >= nodes.flow.nodes.len() {
+        return ret;
+    }
+    let mut queue = VecDeque::new();
+    ret.insert(start);
+    queue.push_back(start);
+    while let Some(here) = queue.pop_front() {
+        for next in successors(nodes, here) {
+            if ret.insert(next) {
+                queue.push_back(next);
+            }
+        }
+    }
+    ret
+}
+
+/// Variables set by taking an edge. Only accepted answers, split variants, successful webhooks and commands set variables,
+/// including the scores a branch option adds to
+fn assigns<'a>(nodes: &'a Nodes, edge: &Edge) -> Vec<&'a str> {
+    let node = &nodes.flow.nodes[edge.from];
+    let mut ret: Vec<&str> = match edge.kind {
+        EdgeKind::Option(_) | EdgeKind::Answered | EdgeKind::Variant(_) => {
+            node.variable.iter().map(String::as_str).collect()
+        }
+        EdgeKind::Succeeded => match &node.node_type {
+            NodeType::Webhook(webhook) => webhook.receive.iter().map(|(n, _)| n.as_str()).collect(),
+            NodeType::Command(command) => command.output.iter().map(String::as_str).collect(),
+            _ => Vec::new(),
+        },
+        EdgeKind::Exhausted | EdgeKind::Timeout | EdgeKind::Band(_) | EdgeKind::Failed => {
+            Vec::new()
+        }
+    };
+    if let (EdgeKind::Option(n), NodeType::Branching(_, options)) = (edge.kind, &node.node_type) {
+        ret.extend(options[n - 1].points.iter().map(|(name, _)| name.as_str()));
+    }
+    ret
+}
+
+/// Every template a node can show, in every locale, each with the guard it's only shown behind
+fn guarded_templates(nodes: &Nodes, node: NodeId) -> Vec<(&str, Option<&Condition>)> {
+    let raw: Vec<(&String, Option<&Condition>)> = match &nodes.flow.nodes[node].node_type {
+        NodeType::Branching(question, options) => std::iter::once((question, None))
+            .chain(options.iter().map(|o| (&o.text, o.guard.as_ref())))
+            .collect(),
+        NodeType::Question(_, _, qs) => qs.iter().map(|q| (q, None)).collect(),
+        NodeType::Terminating(message) => vec![(message, None)],
+        NodeType::Routing(_) | NodeType::Split(_) | NodeType::Webhook(_) | NodeType::Command(_) => {
+            Vec::new()
+        }
+    };
+    raw.into_iter()
+        .flat_map(|(t, guard)| {
+            nodes
+                .flow
+                .catalogues
+                .variants(t)
+                .into_iter()
+                .map(move |t| (t, guard))
+        })
+        .collect()
+}
+
+/// Every template a node can show, in every locale
+pub(crate) fn templates(nodes: &Nodes, node: NodeId) -> Vec<&str> {
+    guarded_templates(nodes, node)
+        .into_iter()
+        .map(|(t, _)| t)
+        .collect()
+}
+
+/// Variables a node's prompts show without a default,
+/// except where an option's guard or an `{{#if}}` block around them ensures they are set
+fn shown_variables(nodes: &Nodes, node: NodeId) -> Vec<String> {
+    let mut ret: Vec<String> = Vec::new();
+    for (template, guard) in guarded_templates(nodes, node) {
+        let guard = guard.and_then(|g| g.ensures_set(true));
+        for (text, ensured) in guarded_texts(template) {
+            for v in required_variables(text) {
+                if guard != Some(v.as_str()) && !ensured.contains(&v) && !ret.contains(&v) {
+                    ret.push(v);
+                }
+            }
+        }
+    }
+    ret
+}
+
+/// A prompt that can show a variable before it has been set
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnsetUse {
+    /// Node whose prompt uses the variable
+    pub node: NodeId,
+    /// Variable name, without the `$`
+    pub variable: String,
+    /// A shortest path from the start to `node` that never sets `variable`
+    pub path: Vec<NodeId>,
+}
+
+impl fmt::Display for UnsetUse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path: Vec<String> = self.path.iter().map(|n| n.to_string()).collect();
+        write!(
+            f,
+            "node {} uses ${} which may not be set, e.g. via {}",
+            self.node,
+            self.variable,
+            path.join(" -> ")
+        )
+    }
+}
+
+/// Variables definitely set on arrival at each node reachable from `start`,
+/// whichever path was taken. Variables required to begin at `start` are set from the start.
+pub fn definitely_set(nodes: &Nodes, start: NodeId) -> BTreeMap<NodeId, BTreeSet<String>> {
+    let live = reachable(nodes, start);
+    // None stands for "everything", the starting point for a must-analysis
+    let mut sets: BTreeMap<NodeId, Option<BTreeSet<String>>> =
+        live.iter().map(|n| (*n, None)).collect();
+    sets.insert(start, Some(nodes.flow.metadata.set_at(start)));
+    let mut queue: VecDeque<NodeId> = vec![start].into();
+    while let Some(here) = queue.pop_front() {
+        let arriving = sets[&here].clone().unwrap_or_default();
+        for edge in edges_from(nodes, here) {
+            if !live.contains(&edge.to) {
+                continue;
+            }
+            let mut leaving = arriving.clone();
+            for v in assigns(nodes, &edge) {
+                leaving.insert(v.into());
+            }
+            let merged = match &sets[&edge.to] {
+                None => leaving,
+                Some(current) => current.intersection(&leaving).cloned().collect(),
+            };
+            if sets[&edge.to].as_ref() != Some(&merged) {
+                sets.insert(edge.to, Some(merged));
+                queue.push_back(edge.to);
+            }
+        }
+    }
+    sets.into_iter()
+        .map(|(n, set)| (n, set.unwrap_or_default()))
+        .collect()
+}
+
+/// Shortest path from `start` to `to` that never sets `variable`
+pub(crate) fn path_without(
+    nodes: &Nodes,
+    start: NodeId,
+    to: NodeId,
+    variable: &str,
+) -> Vec<NodeId> {
+    let mut came_from: BTreeMap<NodeId, NodeId> = BTreeMap::new();
+    let mut queue: VecDeque<NodeId> = vec![start].into();
+    let mut seen: BTreeSet<NodeId> = vec![start].into_iter().collect();
+    while let Some(here) = queue.pop_front() {
+        if here == to {
+            break;
+        }
+        for edge in edges_from(nodes, here) {
+            if edge.to < nodes.flow.nodes.len()
+                && !assigns(nodes, &edge).contains(&variable)
+                && seen.insert(edge.to)
+            {
+                came_from.insert(edge.to, here);
+                queue.push_back(edge.to);
+            }
+        }
+    }
+    let mut ret = vec![to];
+    while let Some(prev) = came_from.get(ret.last().unwrap()) {
+        ret.push(*prev);
+    }
+    ret.reverse();
+    ret
+}
+
+/// Every template variable that some path from `start` reaches before setting it
+pub fn possibly_unset(nodes: &Nodes, start: NodeId) -> Vec<UnsetUse> {
+    let mut ret = Vec::new();
+    for (node, set) in definitely_set(nodes, start) {
+        let used = shown_variables(nodes, node);
+        for variable in used.into_iter().filter(|v| !set.contains(v)) {
+            ret.push(UnsetUse {
+                node,
+                path: path_without(nodes, start, node, &variable),
+                variable,
+            });
+        }
+    }
+    ret
+}
+
+/// How often recorded sessions used each node and edge
+#[derive(Debug)]
+pub struct Coverage {
+    /// Times each node was entered
+    pub nodes: BTreeMap<NodeId, usize>,
+    /// Times each edge was taken
+    pub edges: BTreeMap<Edge, usize>,
+    /// Sessions recorded
+    pub sessions: usize,
+}
+
+impl Coverage {
+    /// Start with every node and edge in the flow at zero
+    pub fn new(nodes: &Nodes) -> Self {
+        Self {
+            nodes: (0..nodes.flow.nodes.len()).map(|n| (n, 0)).collect(),
+            edges: edges(nodes).into_iter().map(|e| (e, 0)).collect(),
+            sessions: 0,
+        }
+    }
+
+    /// Tally one session's transcript
+    pub fn record(&mut self, entries: &[Entry]) {
+        self.sessions += 1;
+        // Last accepted input at each node decides which option was taken
+        let mut last_input = None;
+        for entry in entries {
+            match &entry.event {
+                Event::NodeEntered { node } => *self.nodes.entry(*node).or_default() += 1,
+                Event::Input { raw, .. } => last_input = Some(raw.clone()),
+                Event::Transition {
+                    from,
+                    to: Some(to),
+                    cause,
+                } => {
+                    let kind = match cause {
+                        Cause::Exhausted => EdgeKind::Exhausted,
+                        Cause::Timeout => EdgeKind::Timeout,
+                        Cause::Skipped => EdgeKind::Answered,
+                        Cause::Scored => self.taken(*from, *to, |k| matches!(k, EdgeKind::Band(_))),
+                        Cause::Split => {
+                            self.taken(*from, *to, |k| matches!(k, EdgeKind::Variant(_)))
+                        }
+                        Cause::Succeeded => EdgeKind::Succeeded,
+                        Cause::Failed => EdgeKind::Failed,
+                        Cause::Answer => match last_input.as_ref().and_then(|i| i.parse().ok()) {
+                            Some(n) => self.option(*from, n, *to).unwrap_or(EdgeKind::Answered),
+                            None => EdgeKind::Answered,
+                        },
+                    };
+                    *self
+                        .edges
+                        .entry(Edge {
+                            from: *from,
+                            kind,
+                            to: *to,
+                        })
+                        .or_default() += 1;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The first edge of a kind leading from `from` to `to`
+    fn taken(&self, from: NodeId, to: NodeId, kind: fn(&EdgeKind) -> bool) -> EdgeKind {
+        self.edges
+            .keys()
+            .find(|e| e.from == from && e.to == to && kind(&e.kind))
+            .map_or(EdgeKind::Answered, |e| e.kind)
+    }
+
+    /// The branch option of `from` chosen as number `n` to reach `to`.
+    /// With options hidden by guards, `n` may not be the option's place in the file,
+    /// so failing that it's the first option leading to `to`.
+    fn option(&self, from: NodeId, n: usize, to: NodeId) -> Option<EdgeKind> {
+        let mut options = self
+            .edges
+            .keys()
+            .filter(|e| e.from == from && e.to == to && matches!(e.kind, EdgeKind::Option(_)));
+        let first = options.clone().next()?.kind;
+        Some(
+            options
+                .find(|e| e.kind == EdgeKind::Option(n))
+                .map_or(first, |e| e.kind),
+        )
+    }
+
+    /// Nodes no session entered
+    pub fn unvisited_nodes(&self) -> Vec<NodeId> {
+        self.nodes
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(n, _)| *n)
+            .collect()
+    }
+
+    /// Edges no session took
+    pub fn untaken_edges(&self) -> Vec<&Edge> {
+        self.edges
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(e, _)| e)
+            .collect()
+    }
+}
+
+impl fmt::Display for Coverage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        /// Share of items with a nonzero count
+        fn percent<K>(counts: &BTreeMap<K, usize>) -> usize {
+            let covered = counts.values().filter(|c| **c > 0).count();
+            (covered * 100).checked_div(counts.len()).unwrap_or(100)
+        }
+        writeln!(f, "Sessions: {}", self.sessions)?;
+        writeln!(f, "Node coverage: {}%", percent(&self.nodes))?;
+        for (node, count) in &self.nodes {
+            writeln!(f, "  {:>4}  node {}", count, node)?;
+        }
+        writeln!(f, "Edge coverage: {}%", percent(&self.edges))?;
+        for (edge, count) in &self.edges {
+            writeln!(f, "  {:>4}  {}", count, edge)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transcript::MemorySink;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_simple_paths() {
+        let nodes: Nodes = include_str!("../input.txt").parse().unwrap();
+        let paths = simple_paths(&nodes, 0);
+        assert_eq!(
+            paths.into_iter().collect::<Vec<_>>(),
+            vec![
+                (3, vec![vec![0, 1, 3], vec![0, 3]]),
+                (4, vec![vec![0, 1, 2, 4]]),
+                (5, vec![vec![0, 1, 2, 5]]),
+            ]
+        );
+        assert!(cycles(&nodes).is_empty());
+    }
+
+    #[test]
+    fn test_cycles() {
+        let nodes: Nodes = include_str!("../AdReadiness.txt").parse().unwrap();
+        let found = cycles(&nodes);
+        assert!(found.contains(&vec![5, 6]));
+        assert!(found.contains(&vec![11, 12]));
+        assert!(found.contains(&vec![3, 4, 5, 7]));
+        // Every terminating node is reachable
+        let paths = simple_paths(&nodes, 0);
+        assert_eq!(paths.keys().cloned().collect::<Vec<_>>(), vec![9, 22]);
+        assert!(paths[&9].contains(&vec![0, 2, 8, 9]));
+    }
+
+    #[test]
+    fn test_possibly_unset() {
+        let nodes: Nodes = include_str!("../input.txt").parse().unwrap();
+        assert_eq!(possibly_unset(&nodes, 0), vec![]);
+        // Let node 0 skip straight to the farewell that uses everything
+        let skipping: Nodes = include_str!("../input.txt")
+            .replace("1\n1\n3\nNAME", "1\n1\n4\nNAME")
+            .parse()
+            .unwrap();
+        assert_eq!(
+            possibly_unset(&skipping, 0)
+                .into_iter()
+                .map(|u| u.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "node 4 uses $NAME which may not be set, e.g. via 0 -> 4",
+                "node 4 uses $COLOR which may not be set, e.g. via 0 -> 4",
+                "node 4 uses $QUEST which may not be set, e.g. via 0 -> 4",
+            ]
+        );
+        let ad = Nodes::from_file("AdReadiness.txt").unwrap();
+        assert_eq!(possibly_unset(&ad, 0), vec![]);
+        assert!(definitely_set(&ad, 0)[&3].contains("NAME"));
+        // Shown only once set, by an option's guard or an {{#if}} block
+        let guarded: Nodes = "2\nCHOICE\nHello{{#if NAME}}, $NAME{{/if}}\nCall me $NICK:1 if NICK\nAgain, $AGAIN:1\n3\nBye{{#if COLOR == Red}} $COLOR{{/if}}{{#if X != Y}} $X{{/if}}\n"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            possibly_unset(&guarded, 0)
+                .into_iter()
+                .map(|u| (u.node, u.variable))
+                .collect::<Vec<_>>(),
+            vec![(0, "AGAIN".to_string()), (1, "X".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_coverage() {
+        let sink = MemorySink::default();
+        let mut nodes: Nodes = include_str!("../input.txt").parse().unwrap();
+        let mut coverage = Coverage::new(&nodes);
+        nodes.set_transcript(Some(Box::new(sink.clone())));
+        for input in &["Arthur", "2", ""] {
+            nodes.prompt();
+            nodes.answer(input).unwrap();
+        }
+        coverage.record(&sink.entries());
+        assert_eq!(coverage.unvisited_nodes(), vec![2, 4, 5]);
+        assert_eq!(
+            coverage
+                .untaken_edges()
+                .into_iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "0 -> 3 (exhausted)",
+                "1 -> 2 (option 1)",
+                "2 -> 4 (option 1)",
+                "2 -> 5 (option 2)",
+            ]
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! # command
+//! A command node asks nothing. It runs a local program, then moves on to one node if it exits with status 0
+//! and another if not:
+//! ```txt
+//! 7 // Node type must be 7
+//! ./scripts/open-ticket // Program to run, which the flow's @allow line must list
+//! 9 // Node to go to on exit status 0
+//! 23 // Node to go to otherwise, or if it couldn't be run
+//! args --customer $NAME "high priority" // Arguments split on spaces, each a template, none if left out
+//! env NAME EMAIL // Variables to pass as environment variables of the same name
+//! output TICKET // Set $TICKET to what the program printed, trimmed
+//! ```
+//! Each setting line is optional. The program is run directly rather than through a shell,
+//! so a variable can't add arguments or commands of its own.
+//! Apart from `PATH` and the variables named by `env`, the program gets an empty environment.
+//!
+//! Flow files are data and might come from anywhere, so running programs takes three things:
+//! - every program the flow runs listed on an `@allow` line before its first node, `@allow ./scripts/open-ticket`,
+//!   or the flow won't load
+//! - winnowdemo built with the `command` cargo feature
+//! - commands turned on for the session, with `--allow-commands` or
+//!   [`Nodes::set_commands_enabled`](../struct.Nodes.html#method.set_commands_enabled)
+//!
+//! Without the last two, command nodes go to their failure node, and the transcript says why.
+
+use crate::{Assignments, Env, NodeId};
+
+/// What to run and what to do with the result
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Command {
+    /// Program to run
+    pub(crate) program: String,
+    /// Node to go to on exit status 0
+    pub(crate) success: NodeId,
+    /// Node to go to otherwise
+    pub(crate) failure: NodeId,
+    /// Argument templates
+    pub(crate) args: Vec<String>,
+    /// Variables to pass as environment variables
+    pub(crate) env: Vec<String>,
+    /// Variable to set to the program's output
+    pub(crate) output: Option<String>,
+}
+
+impl Command {
+    /// Construct a command with no settings
+    pub(crate) fn new(program: &str, success: NodeId, failure: NodeId) -> Self {
+        Self {
+            program: program.into(),
+            success,
+            failure,
+            args: Vec::new(),
+            env: Vec::new(),
+            output: None,
+        }
+    }
+
+    /// Run the program with already resolved `args`, reading the variable to set from its output.
+    /// An error says why the run counts as a failure.
+    #[cfg(feature = "command")]
+    pub(crate) fn run(&self, args: &[String], env: &Env) -> Result<Assignments, String> {
+        let mut command = std::process::Command::new(&self.program);
+        command.args(args).env_clear();
+        if let Some(path) = std::env::var_os("PATH") {
+            command.env("PATH", path);
+        }
+        for name in &self.env {
+            if let Some(value) = env.get(name) {
+                command.env(name, value.to_string());
+            }
+        }
+        let output = command
+            .stdin(std::process::Stdio::null())
+            .output()
+            .map_err(|e| format!("{}: {}", self.program, e))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!(
+                "{} exited with {}: {}",
+                self.program,
+                output.status,
+                stderr.trim()
+            ));
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(self
+            .output
+            .iter()
+            .map(|name| (name.clone(), stdout.trim().into()))
+            .collect())
+    }
+
+    /// Running programs needs the `command` feature
+    #[cfg(not(feature = "command"))]
+    pub(crate) fn run(&self, _args: &[String], _env: &Env) -> Result<Assignments, String> {
+        Err(format!(
+            "{}: built without the `command` feature",
+            self.program
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{transcript::Event, Nodes, Step};
+    use pretty_assertions::assert_eq;
+
+    /// A flow asking a name, then running `program` with it
+    fn flow(program: &str, settings: &str) -> String {
+        format!(
+            "@allow {}\n1\n1\n1\nNAME\nWhat is your name?\n/* */\n7\n{}\n2\n3\n{}3\nDone, $GREETING\n3\nSomething went wrong\n",
+            program, program, settings
+        )
+    }
+
+    #[test]
+    fn test_allowlist() {
+        assert!(flow("echo", "").parse::<Nodes>().is_ok());
+        let unlisted = flow("echo", "").replace("@allow echo", "@allow true");
+        let error = unlisted.parse::<Nodes>().unwrap_err().to_string();
+        assert!(error.contains("echo isn't allowed"), "{}", error);
+        // Not allowed at all
+        let error = flow("echo", "")
+            .lines()
+            .skip(1)
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert!(error.parse::<Nodes>().is_err());
+    }
+
+    #[test]
+    fn test_commands_disabled() {
+        let sink = crate::transcript::MemorySink::default();
+        let mut nodes: Nodes = flow("echo", "output GREETING\n").parse().unwrap();
+        nodes.set_transcript(Some(Box::new(sink.clone())));
+        assert_eq!(nodes.answer("Arthur"), Ok(Step::Transition(3)));
+        assert_eq!(nodes.env().get("GREETING"), None);
+        assert!(sink
+            .entries()
+            .iter()
+            .any(|e| matches!(e.event, Event::Failed { node: 1, .. })));
+    }
+
+    #[cfg(all(feature = "command", unix))]
+    #[test]
+    fn test_command() {
+        let mut nodes: Nodes = flow("echo", "args Hello, $NAME\noutput GREETING\n")
+            .parse()
+            .unwrap();
+        nodes.set_commands_enabled(true);
+        assert_eq!(nodes.answer("Arthur"), Ok(Step::Transition(2)));
+        assert_eq!(nodes.prompt().text(), "Done, Hello, Arthur");
+        // Only variables named reach the environment, and nothing else does
+        let mut nodes: Nodes = flow("printenv", "args NAME\nenv NAME\noutput GREETING\n")
+            .parse()
+            .unwrap();
+        nodes.set_commands_enabled(true);
+        nodes.answer("Sir Robin").unwrap();
+        assert_eq!(nodes.env().get_variable("GREETING"), "Sir Robin");
+        let mut nodes: Nodes = flow("printenv", "args \"HOME\"\n").parse().unwrap();
+        nodes.set_commands_enabled(true);
+        assert_eq!(nodes.answer("Sir Robin"), Ok(Step::Transition(3)));
+        // Non-zero exit status
+        let mut nodes: Nodes = flow("false", "").parse().unwrap();
+        nodes.set_commands_enabled(true);
+        assert_eq!(nodes.answer("Arthur"), Ok(Step::Transition(3)));
+    }
+}
@@ -0,0 +1,360 @@
+//! # condition
+//! Part of a template can depend on earlier answers:
+//! ```txt
+//! Google Ad ID{{#if N7}} (done){{/if}}
+//! {{#if N0 == "Get Started"}}Welcome back{{else}}Hello{{/if}}, $NAME
+//! ```
+//! `{{#if NAME}}` holds when `NAME` is set to something other than blank text, an empty list,
+//! or a flag that reads as no, like `false`, `no` or `0`, and `{{#if !NAME}}` when it isn't.
+//! `{{#if NAME == value}}` and `{{#if NAME != value}}` compare its text, the value quoted if it has spaces.
+//! `{{else}}` is optional and blocks can be nested.
+//!
+//! The same conditions guard branch options, see [`Nodes`](../struct.Nodes.html).
+//!
+//! Flows are checked when they are loaded, so a malformed block is an error then rather than odd text later:
+//! ```
+//! # use winnow_sm::condition::check;
+//! assert!(check("{{#if NAME}}Hi, $NAME{{/if}}").is_ok());
+//! assert!(check("{{#if NAME}}Hi, $NAME").is_err());
+//! ```
+
+use std::str::FromStr;
+
+use crate::{is_variable_name, Env, Value};
+
+/// Opens and closes every tag
+const OPEN: &str = "{{";
+const CLOSE: &str = "}}";
+
+/// What an `{{#if}}` or an option guard tests
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Condition {
+    /// Variable tested
+    name: String,
+    /// Whether it should equal or differ from a value, or just be set
+    compare: Option<(bool, String)>,
+    /// Holds when the test fails instead, from a leading `!`
+    negated: bool,
+}
+
+impl Condition {
+    /// Whether the condition holds for `env`
+    pub(crate) fn holds(&self, env: &Env) -> bool {
+        let value = env.get(&self.name);
+        let ret = match &self.compare {
+            Some((equal, expected)) => {
+                let actual = value.map(Value::to_string).unwrap_or_default();
+                (&actual == expected) == *equal
+            }
+            None => match value {
+                None => false,
+                Some(Value::List(items)) => !items.is_empty(),
+                Some(v) => v.as_bool() != Some(false) && !v.to_string().is_empty(),
+            },
+        };
+        ret != self.negated
+    }
+
+    /// Variable the condition ensures is set when it holds, or when it doesn't if `holds` is false.
+    /// Being set and equal to some text, or different from blank, ensures it.
+    pub(crate) fn ensures_set(&self, holds: bool) -> Option<&str> {
+        let passed = holds != self.negated;
+        let set = match &self.compare {
+            None => passed,
+            Some((equal, value)) => (passed == *equal) != value.is_empty(),
+        };
+        if set {
+            Some(&self.name)
+        } else {
+            None
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    /// Read a condition like `NAME`, `!NAME` or `NAME == "some value"`
+    fn from_str(condition: &str) -> Result<Self, Self::Err> {
+        let trimmed = condition.trim();
+        let (negated, test) = match trimmed.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, trimmed),
+        };
+        let (name, compare) = if let Some((name, value)) = test.split_once("!=") {
+            (name, Some((false, value)))
+        } else if let Some((name, value)) = test.split_once("==") {
+            (name, Some((true, value)))
+        } else {
+            (test, None)
+        };
+        let name = name.trim();
+        if !is_variable_name(name) {
+            return Err(format!("`{}` needs a variable name", trimmed));
+        }
+        let compare = compare.map(|(equal, value)| {
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+            (equal, value.to_string())
+        });
+        Ok(Self {
+            name: name.into(),
+            compare,
+            negated,
+        })
+    }
+}
+
+/// Piece of a template
+#[derive(Debug, PartialEq)]
+enum Block<'a> {
+    /// Shown as is
+    Text(&'a str),
+    /// Shows one branch or the other
+    If {
+        /// Condition to choose by
+        test: Condition,
+        /// Shown if it holds
+        then: Vec<Block<'a>>,
+        /// Shown if it doesn't
+        otherwise: Vec<Block<'a>>,
+    },
+}
+
+/// An `{{#if}}` still waiting for its `{{/if}}`
+struct Open<'a> {
+    /// Condition to choose by
+    test: Condition,
+    /// Blocks before any `{{else}}`
+    then: Vec<Block<'a>>,
+    /// Blocks after the `{{else}}`, once seen
+    otherwise: Option<Vec<Block<'a>>>,
+}
+
+/// Split a template into text and conditional blocks
+fn parse(template: &str) -> Result<Vec<Block<'_>>, String> {
+    let mut root = Vec::new();
+    let mut stack: Vec<Open> = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find(OPEN) {
+        let after = &rest[start + OPEN.len()..];
+        let len = after
+            .find(CLOSE)
+            .ok_or_else(|| format!("`{}` is never closed with `{}`", OPEN, CLOSE))?;
+        let tag = after[..len].trim();
+        let blocks = match stack.last_mut() {
+            Some(open) => open.otherwise.as_mut().unwrap_or(&mut open.then),
+            None => &mut root,
+        };
+        if start > 0 {
+            blocks.push(Block::Text(&rest[..start]));
+        }
+        if let Some(condition) = tag.strip_prefix("#if ") {
+            stack.push(Open {
+                test: condition
+                    .parse()
+                    .map_err(|e| format!("`{{{{#if}}}}`: {}", e))?,
+                then: Vec::new(),
+                otherwise: None,
+            });
+        } else if tag == "else" {
+            match stack.last_mut() {
+                Some(open) if open.otherwise.is_none() => open.otherwise = Some(Vec::new()),
+                Some(_) => return Err("`{{#if}}` has more than one `{{else}}`".into()),
+                None => return Err("`{{else}}` without an `{{#if}}`".into()),
+            }
+        } else if tag == "/if" {
+            let open = stack.pop().ok_or("`{{/if}}` without an `{{#if}}`")?;
+            let block = Block::If {
+                test: open.test,
+                then: open.then,
+                otherwise: open.otherwise.unwrap_or_default(),
+            };
+            match stack.last_mut() {
+                Some(outer) => outer
+                    .otherwise
+                    .as_mut()
+                    .unwrap_or(&mut outer.then)
+                    .push(block),
+                None => root.push(block),
+            }
+        } else {
+            return Err(format!("unknown tag `{{{{{}}}}}`", tag));
+        }
+        rest = &after[len + CLOSE.len()..];
+    }
+    if !stack.is_empty() {
+        return Err("`{{#if}}` is never closed with `{{/if}}`".into());
+    }
+    if !rest.is_empty() {
+        root.push(Block::Text(rest));
+    }
+    Ok(root)
+}
+
+/// Make sure every block in a template is well formed
+pub fn check(template: &str) -> Result<(), String> {
+    parse(template).map(|_| ())
+}
+
+/// The text each block shows for `env`
+fn select(blocks: &[Block], env: &Env, ret: &mut String) {
+    for block in blocks {
+        match block {
+            Block::Text(text) => ret.push_str(text),
+            Block::If {
+                test,
+                then,
+                otherwise,
+            } => {
+                if test.holds(env) {
+                    select(then, env, ret)
+                } else {
+                    select(otherwise, env, ret)
+                }
+            }
+        }
+    }
+}
+
+/// Gather each piece of text in `blocks`, with the variables the conditions around it ensure are set
+fn guarded<'a>(
+    blocks: &[Block<'a>],
+    ensured: &mut Vec<String>,
+    ret: &mut Vec<(&'a str, Vec<String>)>,
+) {
+    for block in blocks {
+        match block {
+            Block::Text(text) => ret.push((text, ensured.clone())),
+            Block::If {
+                test,
+                then,
+                otherwise,
+            } => {
+                for (holds, branch) in [(true, then), (false, otherwise)].iter() {
+                    let name = test.ensures_set(*holds);
+                    ensured.extend(name.map(String::from));
+                    guarded(branch, ensured, ret);
+                    if name.is_some() {
+                        ensured.pop();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Each piece of text a template can show, with the variables the `{{#if}}` blocks around it ensure are set.
+/// A malformed template is one piece ensuring nothing.
+pub(crate) fn guarded_texts(template: &str) -> Vec<(&str, Vec<String>)> {
+    match parse(template) {
+        Ok(blocks) => {
+            let mut ret = Vec::new();
+            guarded(&blocks, &mut Vec::new(), &mut ret);
+            ret
+        }
+        Err(_) => vec![(template, Vec::new())],
+    }
+}
+
+/// The template with every block decided for `env`, variables left in place.
+/// A malformed template is left as it is.
+pub(crate) fn choose(template: &str, env: &Env) -> String {
+    match parse(template) {
+        Ok(blocks) => {
+            let mut ret = String::new();
+            select(&blocks, env, &mut ret);
+            ret
+        }
+        Err(_) => template.into(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_choose() {
+        let mut env = Env::default();
+        env.set_variable("N7", "Okay".into());
+        env.set_variable("COLOR", "Red".into());
+        env.set_value("ASKED", false);
+        let template = "{{#if N7}}done{{else}}todo{{/if}}, {{#if COLOR == Red}}red{{#if ASKED}} again{{/if}}{{else}}other{{/if}}";
+        assert_eq!(choose(template, &env), "done, red");
+        assert_eq!(choose(template, &Env::default()), "todo, other");
+        assert_eq!(
+            choose("{{#if COLOR != \"I mean blue\"}}Not blue{{/if}}", &env),
+            "Not blue"
+        );
+        assert_eq!(
+            choose("{{#if !ASKED}}First time{{/if}}", &env),
+            "First time"
+        );
+        assert_eq!(choose("{{#if N7}}left alone", &env), "{{#if N7}}left alone");
+    }
+
+    #[test]
+    fn test_guarded_texts() {
+        /// Each piece with the variables ensured, space separated
+        fn texts(template: &str) -> Vec<(&str, String)> {
+            guarded_texts(template)
+                .into_iter()
+                .map(|(text, ensured)| (text, ensured.join(" ")))
+                .collect()
+        }
+        assert_eq!(
+            texts(
+                "Hi{{#if NAME}} $NAME{{#if !COLOR}}{{else}} in $COLOR{{/if}}{{else}} there{{/if}}"
+            ),
+            vec![
+                ("Hi", "".into()),
+                (" $NAME", "NAME".into()),
+                (" in $COLOR", "NAME COLOR".into()),
+                (" there", "".into()),
+            ]
+        );
+        assert_eq!(
+            texts("{{#if A == x}}$A{{/if}}{{#if B != x}}$B{{else}}$B{{/if}}{{#if C != \"\"}}$C{{/if}}"),
+            vec![
+                ("$A", "A".into()),
+                ("$B", "".into()),
+                ("$B", "B".into()),
+                ("$C", "C".into()),
+            ]
+        );
+        assert_eq!(texts("{{#if A}}$A"), vec![("{{#if A}}$A", "".into())]);
+    }
+
+    #[test]
+    fn test_check() {
+        assert_eq!(
+            check("{{#if N7}}a{{else}}b{{else}}c{{/if}}"),
+            Err("`{{#if}}` has more than one `{{else}}`".into())
+        );
+        assert_eq!(
+            check("a{{/if}}"),
+            Err("`{{/if}}` without an `{{#if}}`".into())
+        );
+        assert_eq!(
+            check("{{#if lower}}a{{/if}}"),
+            Err("`{{#if}}`: `lower` needs a variable name".into())
+        );
+        assert_eq!(
+            check("{{#if 7X}}a{{/if}}"),
+            Err("`{{#if}}`: `7X` needs a variable name".into())
+        );
+        assert_eq!(
+            check("{{#each X}}"),
+            Err("unknown tag `{{#each X}}`".into())
+        );
+        assert_eq!(
+            check("{{#if X}"),
+            Err("`{{` is never closed with `}}`".into())
+        );
+    }
+}
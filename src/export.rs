@@ -0,0 +1,157 @@
+//! # export
+//! [`Nodes::outcome`](../struct.Nodes.html#method.outcome) captures how a session ended:
+//! the terminating node reached, the path taken, every variable collected and the scores.
+//! An [`Outcome`](struct.Outcome.html) can be written as JSON, or appended as one row of a CSV file
+//! shared by many sessions for opening in a spreadsheet.
+
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+};
+
+use crate::{NodeId, Value};
+
+/// Columns before the variables in a CSV file
+const FIXED_COLUMNS: [&str; 2] = ["terminating_node", "path"];
+
+/// How a session ended
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Outcome {
+    /// Terminating node reached, or `None` if the session stopped before one
+    pub terminating_node: Option<NodeId>,
+    /// Nodes entered, in order
+    pub path: Vec<NodeId>,
+    /// Every variable set
+    pub variables: BTreeMap<String, Value>,
+    /// Every score in the flow, zero if nothing added to it
+    pub scores: BTreeMap<String, i64>,
+}
+
+impl Outcome {
+    /// Pretty-printed JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Should serialize outcome")
+    }
+
+    /// One CSV row, with a column for each of `variables` in order
+    pub fn csv_row(&self, variables: &[String]) -> String {
+        let path: Vec<String> = self.path.iter().map(|n| n.to_string()).collect();
+        let mut fields = vec![
+            self.terminating_node
+                .map(|n| n.to_string())
+                .unwrap_or_default(),
+            path.join(" -> "),
+        ];
+        fields.extend(variables.iter().map(|v| {
+            self.variables
+                .get(v)
+                .map(Value::to_string)
+                .unwrap_or_default()
+        }));
+        csv_line(&fields)
+    }
+
+    /// Add a row to the CSV file at `path`, creating it with a header if needed.
+    /// A new file gets a column for each of `variables`; an existing one keeps the columns it has.
+    pub fn append_csv(&self, path: &Path, variables: &[String]) -> io::Result<()> {
+        let existing = match File::open(path) {
+            Ok(f) => BufReader::new(f).lines().next().transpose()?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        let columns = match existing {
+            Some(header) => split_csv_header(&header),
+            None => {
+                let mut header: Vec<String> = FIXED_COLUMNS.iter().map(|c| c.to_string()).collect();
+                header.extend(variables.iter().cloned());
+                writeln!(file, "{}", csv_line(&header))?;
+                header
+            }
+        };
+        let variables: Vec<String> = columns.into_iter().skip(FIXED_COLUMNS.len()).collect();
+        writeln!(file, "{}", self.csv_row(&variables))
+    }
+}
+
+/// Quote a field if it needs it
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.into()
+    }
+}
+
+/// Fields joined into a line, without the line ending
+fn csv_line(fields: &[String]) -> String {
+    fields
+        .iter()
+        .map(|f| csv_field(f))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Column names from a header line. Variable names never need quoting, so this doesn't unquote.
+fn split_csv_header(header: &str) -> Vec<String> {
+    header.trim_end().split(',').map(String::from).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Nodes;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+
+    #[test]
+    fn test_outcome() {
+        let mut nodes: Nodes = include_str!("../input.txt").parse().unwrap();
+        for input in &["Arthur, King", "1", "1"] {
+            nodes.answer(input).unwrap();
+        }
+        // Not over until the goodbye is dismissed
+        assert_eq!(nodes.outcome().terminating_node, None);
+        nodes.answer("").unwrap();
+        let outcome = nodes.outcome();
+        assert_eq!(outcome.terminating_node, Some(4));
+        assert_eq!(outcome.path, vec![0, 1, 2, 4]);
+        let json: serde_json::Value = serde_json::from_str(&outcome.to_json()).unwrap();
+        assert_eq!(json["variables"]["NAME"], "Arthur, King");
+        assert_eq!(
+            outcome.csv_row(&nodes.flow().variables()),
+            "4,0 -> 1 -> 2 -> 4,\"Arthur, King\",The Holy Grail,Red"
+        );
+    }
+
+    #[test]
+    fn test_append_csv() {
+        let file = std::env::temp_dir().join(format!("winnow-export-{}.csv", std::process::id()));
+        let _ = fs::remove_file(&file);
+        let variables = vec!["NAME".to_string(), "QUEST".to_string()];
+        let mut first = Outcome {
+            terminating_node: Some(3),
+            path: vec![0, 1, 3],
+            variables: BTreeMap::new(),
+            scores: BTreeMap::new(),
+        };
+        first.variables.insert("NAME".into(), "Robin".into());
+        first.append_csv(&file, &variables).unwrap();
+        // The file's columns win over the ones asked for
+        let second = Outcome {
+            terminating_node: None,
+            path: vec![0],
+            variables: BTreeMap::new(),
+            scores: BTreeMap::new(),
+        };
+        second.append_csv(&file, &["OTHER".to_string()]).unwrap();
+        assert_eq!(
+            fs::read_to_string(&file).unwrap(),
+            "terminating_node,path,NAME,QUEST\n3,0 -> 1 -> 3,Robin,\n,0,,\n"
+        );
+        fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,218 @@
+//! # filter
+//! A template variable can be written `${NAME}` and passed through filters, applied left to right:
+//! ```txt
+//! You may pass, ${NAME|trim|capitalize}, on your quest for ${QUEST|lower|truncate:20}.
+//! Hello, ${NAME|default:"friend"}!
+//! ```
+//! Built in are `capitalize`, `upper`, `lower`, `trim`, `truncate:<length>`, and `default:<text>`,
+//! which stands in for a variable that is unset or blank.
+//! An argument can be quoted to include `|` or `}`.
+//!
+//! An application can add its own to a [`Flow`](../struct.Flow.html) before starting sessions:
+//! ```
+//! # use winnow_sm::Flow;
+//! let mut flow: Flow = include_str!("../input.txt").parse().unwrap();
+//! flow.filters_mut().register("shout", |text, _| format!("{}!", text.to_uppercase()));
+//! assert!(flow.filters().contains("shout"));
+//! ```
+//! An unknown filter leaves the text as it is. The `check` command reports them.
+
+use std::{collections::HashMap, fmt, sync::Arc};
+
+/// Filters every flow has
+const BUILT_IN: [&str; 6] = [
+    "capitalize",
+    "upper",
+    "lower",
+    "trim",
+    "truncate",
+    "default",
+];
+
+/// A filter: takes the text so far and the argument after the `:`, if any
+pub type FilterFn = dyn Fn(&str, Option<&str>) -> String + Send + Sync;
+
+/// Custom filters, by name
+#[derive(Clone, Default)]
+pub struct Filters {
+    /// Filters added by the application
+    custom: HashMap<String, Arc<FilterFn>>,
+}
+
+impl Filters {
+    /// Add a filter, replacing any with the same name, built in or not.
+    /// `default` is the exception, as it acts on unset variables rather than text.
+    pub fn register(
+        &mut self,
+        name: &str,
+        filter: impl Fn(&str, Option<&str>) -> String + Send + Sync + 'static,
+    ) {
+        self.custom.insert(name.into(), Arc::new(filter));
+    }
+
+    /// Whether a filter by this name exists
+    pub fn contains(&self, name: &str) -> bool {
+        BUILT_IN.contains(&name) || self.custom.contains_key(name)
+    }
+
+    /// Run a filter over `text`, or `None` if there isn't one by this name
+    pub fn apply(&self, name: &str, text: &str, arg: Option<&str>) -> Option<String> {
+        if let Some(filter) = self.custom.get(name) {
+            return Some(filter(text, arg));
+        }
+        let ret = match name {
+            "capitalize" => {
+                let mut chars = text.chars();
+                match chars.next() {
+                    Some(first) => first.to_uppercase().chain(chars).collect(),
+                    None => String::new(),
+                }
+            }
+            "upper" => text.to_uppercase(),
+            "lower" => text.to_lowercase(),
+            "trim" => text.trim().into(),
+            "truncate" => match arg.and_then(|a| a.trim().parse::<usize>().ok()) {
+                Some(len) if text.chars().count() > len => {
+                    let mut ret: String = text.chars().take(len.saturating_sub(1)).collect();
+                    ret.push('…');
+                    ret
+                }
+                _ => text.into(),
+            },
+            "default" if text.is_empty() => arg.unwrap_or_default().into(),
+            "default" => text.into(),
+            _ => return None,
+        };
+        Some(ret)
+    }
+}
+
+impl fmt::Debug for Filters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names: Vec<&String> = self.custom.keys().collect();
+        names.sort();
+        f.debug_struct("Filters").field("custom", &names).finish()
+    }
+}
+
+impl PartialEq for Filters {
+    /// Functions can't be compared, so registries with the same names are equal
+    fn eq(&self, other: &Self) -> bool {
+        self.custom.len() == other.custom.len()
+            && self.custom.keys().all(|k| other.custom.contains_key(k))
+    }
+}
+
+/// One filter in a `${...}` expression
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Call<'a> {
+    /// Filter name
+    pub(crate) name: &'a str,
+    /// Text after the `:`, unquoted
+    pub(crate) arg: Option<String>,
+}
+
+/// Length of a `${...}` expression's contents, up to the closing `}` outside quotes
+pub(crate) fn expression_len(s: &str) -> Option<usize> {
+    let mut quoted = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '}' if !quoted => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Split `NAME|filter:arg|...` into the variable name and its filters
+pub(crate) fn parse_expression(expression: &str) -> (&str, Vec<Call<'_>>) {
+    let mut pieces = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in expression.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '|' if !quoted => {
+                pieces.push(&expression[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    pieces.push(&expression[start..]);
+    let name = pieces[0].trim();
+    let calls = pieces[1..]
+        .iter()
+        .map(|piece| match piece.split_once(':') {
+            Some((name, arg)) => {
+                let arg = arg.trim();
+                let arg = arg
+                    .strip_prefix('"')
+                    .and_then(|a| a.strip_suffix('"'))
+                    .unwrap_or(arg);
+                Call {
+                    name: name.trim(),
+                    arg: Some(arg.into()),
+                }
+            }
+            None => Call {
+                name: piece.trim(),
+                arg: None,
+            },
+        })
+        .collect();
+    (name, calls)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_built_in() {
+        let filters = Filters::default();
+        assert_eq!(
+            filters.apply("capitalize", "élodie", None),
+            Some("Élodie".into())
+        );
+        assert_eq!(filters.apply("upper", "grail", None), Some("GRAIL".into()));
+        assert_eq!(filters.apply("trim", "  x ", None), Some("x".into()));
+        assert_eq!(
+            filters.apply("truncate", "The Holy Grail", Some("8")),
+            Some("The Hol…".into())
+        );
+        assert_eq!(
+            filters.apply("truncate", "Grail", Some("many")),
+            Some("Grail".into())
+        );
+        assert_eq!(filters.apply("nope", "Grail", None), None);
+        let mut custom = Filters::default();
+        custom.register("upper", |t, _| t.to_lowercase());
+        assert_eq!(custom.apply("upper", "GRAIL", None), Some("grail".into()));
+        assert_ne!(custom, filters);
+    }
+
+    #[test]
+    fn test_parse_expression() {
+        assert_eq!(
+            parse_expression(r#"NAME | trim|default:"a | b""#),
+            (
+                "NAME",
+                vec![
+                    Call {
+                        name: "trim",
+                        arg: None
+                    },
+                    Call {
+                        name: "default",
+                        arg: Some("a | b".into())
+                    },
+                ]
+            )
+        );
+        assert_eq!(expression_len(r#"NAME|default:"}"} rest"#), Some(16));
+        assert_eq!(expression_len("NAME"), None);
+    }
+}
@@ -0,0 +1,179 @@
+//! # hooks
+//! Attach [`Hooks`](trait.Hooks.html) with [`Nodes::set_hooks`](../struct.Nodes.html#method.set_hooks)
+//! to be told as a session enters and leaves nodes, sets variables, moves on and finishes.
+//! Every method does nothing by default, so implement only the ones needed.
+//! [`on_transition`](trait.Hooks.html#method.on_transition) can also veto a move, leaving the session
+//! where it is with its prompt showing again, or redirect it to another node.
+//!
+//! A node in the flow file can name actions for the host to carry out when it is entered:
+//! ```txt
+//! @action create_ticket
+//! 3
+//! #customer_service
+//! ```
+//! [`Actions`](struct.Actions.html) binds a handler to each name:
+//! ```
+//! # use winnow_sm::{hooks::Actions, Nodes};
+//! # use std::sync::{Arc, Mutex};
+//! let tickets = Arc::new(Mutex::new(Vec::new()));
+//! let opened = tickets.clone();
+//! let mut actions = Actions::default();
+//! actions.bind("create_ticket", move |node, env| {
+//!     opened.lock().unwrap().push((node, env.get_variable("NAME")));
+//! });
+//! let mut nodes: Nodes = "@action create_ticket\n3\nWe will be in touch\n".parse().unwrap();
+//! nodes.set_hooks(Some(Box::new(actions)));
+//! nodes.prompt();
+//! assert_eq!(*tickets.lock().unwrap(), vec![(0, "NAME".to_string())]);
+//! ```
+
+use std::{collections::HashMap, fmt};
+
+use crate::{export::Outcome, transcript::Cause, Env, NodeId, Value};
+
+/// What should happen to a transition
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Decision {
+    /// Go ahead as planned
+    Allow,
+    /// Stay on the current node and show its prompt again from the first
+    Veto,
+    /// Go to this node instead
+    Redirect(NodeId),
+}
+
+/// Called by a session as things happen. `env` is the variables at the time.
+pub trait Hooks: Send {
+    /// Arrived at a node
+    fn on_enter(&mut self, _node: NodeId, _env: &Env) {}
+    /// Left a node, whether for another or to finish
+    fn on_exit(&mut self, _node: NodeId, _env: &Env) {}
+    /// A variable was set, by an answer, a score or a split
+    fn on_variable_set(&mut self, _name: &str, _value: &Value) {}
+    /// About to move from `from` to `to`, or finish if `to` is `None`. A redirect isn't asked about again,
+    /// and one to a node the flow doesn't have is ignored. `env` already holds what the move would set,
+    /// such as the answer and any points, but nothing is stored if it's vetoed.
+    fn on_transition(
+        &mut self,
+        _from: NodeId,
+        _to: Option<NodeId>,
+        _cause: Cause,
+        _env: &Env,
+    ) -> Decision {
+        Decision::Allow
+    }
+    /// Execution has ended
+    fn on_terminate(&mut self, _outcome: &Outcome) {}
+    /// Arrived at a node naming this action with `@action`, just after `on_enter`
+    fn on_action(&mut self, _action: &str, _node: NodeId, _env: &Env) {}
+}
+
+/// A handler for a named action: gets the node naming it and the variables
+pub type ActionFn = dyn FnMut(NodeId, &Env) + Send;
+
+/// Handlers for named actions, by name. Actions nothing is bound to are ignored.
+#[derive(Default)]
+pub struct Actions {
+    /// Bound handlers
+    handlers: HashMap<String, Box<ActionFn>>,
+}
+
+impl Actions {
+    /// Run `handler` whenever a node naming `action` is entered, replacing any bound before
+    pub fn bind(&mut self, action: &str, handler: impl FnMut(NodeId, &Env) + Send + 'static) {
+        self.handlers.insert(action.into(), Box::new(handler));
+    }
+
+    /// Whether a handler is bound to `action`
+    pub fn is_bound(&self, action: &str) -> bool {
+        self.handlers.contains_key(action)
+    }
+}
+
+impl fmt::Debug for Actions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names: Vec<&String> = self.handlers.keys().collect();
+        names.sort();
+        f.debug_struct("Actions").field("handlers", &names).finish()
+    }
+}
+
+impl Hooks for Actions {
+    fn on_action(&mut self, action: &str, node: NodeId, env: &Env) {
+        if let Some(handler) = self.handlers.get_mut(action) {
+            handler(node, env);
+        }
+    }
+}
+
+/// Optional hooks held by `Nodes`, so `Nodes` can keep its derives
+#[derive(Default)]
+pub(crate) struct HookSet(pub(crate) Option<Box<dyn Hooks>>);
+
+impl HookSet {
+    /// Whether any hooks are attached
+    pub(crate) fn is_set(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Tell the hooks about arriving at a node and its actions
+    pub(crate) fn enter(&mut self, node: NodeId, actions: &[String], env: &Env) {
+        if let Some(hooks) = &mut self.0 {
+            hooks.on_enter(node, env);
+            for action in actions {
+                hooks.on_action(action, node, env);
+            }
+        }
+    }
+
+    /// Tell the hooks about leaving a node
+    pub(crate) fn exit(&mut self, node: NodeId, env: &Env) {
+        if let Some(hooks) = &mut self.0 {
+            hooks.on_exit(node, env);
+        }
+    }
+
+    /// Tell the hooks about a variable being set
+    pub(crate) fn variable_set(&mut self, name: &str, value: &Value) {
+        if let Some(hooks) = &mut self.0 {
+            hooks.on_variable_set(name, value);
+        }
+    }
+
+    /// Ask the hooks about a transition, allowing it if there are none
+    pub(crate) fn transition(
+        &mut self,
+        from: NodeId,
+        to: Option<NodeId>,
+        cause: Cause,
+        env: &Env,
+    ) -> Decision {
+        match &mut self.0 {
+            Some(hooks) => hooks.on_transition(from, to, cause, env),
+            None => Decision::Allow,
+        }
+    }
+
+    /// Tell the hooks execution has ended
+    pub(crate) fn terminate(&mut self, outcome: &Outcome) {
+        if let Some(hooks) = &mut self.0 {
+            hooks.on_terminate(outcome);
+        }
+    }
+}
+
+impl fmt::Debug for HookSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(_) => write!(f, "HookSet(Some(..))"),
+            None => write!(f, "HookSet(None)"),
+        }
+    }
+}
+
+// What is listening to a session doesn't change the session
+impl PartialEq for HookSet {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
@@ -13,7 +13,7 @@
 //!
 //! ## Input file
 //!
-//! This file contains nodes in one of [three types](https://deciduously.github.io/winnowdemo/winnow_sm/enum.NodeType.html): Question, Branching, or Terminating.
+//! This file contains nodes in one of [seven types](https://deciduously.github.io/winnowdemo/winnow_sm/enum.NodeType.html): Question, Branching, Terminating, Routing, Split, Webhook, or Command.
 //! Note - comments here are for demonstration only and are not (yet) supported.
 //! The `Question` type prompts for a string input, and can accept zero or more responses if prompt left blank:
 //! ```txt
@@ -38,23 +38,152 @@
 //! 3 // Node type must be 3
 //! You may pass, $NAME who loves $COLOR, on your noble quest for the $QUEST.  // Exit message
 //! ```
+//! Branch options can add points to named scores, kept as whole numbers among the variables:
+//! ```txt
+//! Yes, we run ads:5 +4 READY +1 SPEND // Adds 4 to $READY and 1 to $SPEND
+//! Not yet:5 -1 READY
+//! ```
+//! The `Routing` type asks nothing, it moves straight on to the first band its score falls in.
+//! A score nothing has added to is 0, and one outside every band ends execution:
+//! ```txt
+//! 4 // Node type must be 4
+//! READY // Score to route by
+//! 0-5:6 // Not ready, from 0 to 5 inclusive
+//! 6-10:7 // Partially ready
+//! 11+:8 // 11 or more
+//! *:9 // Anything else, like a negative score
+//! ```
+//! The `Split` type asks nothing either, it picks a variant at random by weight and records its name,
+//! for trying out two versions of part of a flow. See the [`split`](split/index.html) module:
+//! ```txt
+//! 5 // Node type must be 5
+//! GREETING // Variable to record the chosen variant in
+//! Formal:1 weight 3 // Variant name:destination, taken 3 times in 4
+//! Casual:2 // A weight of 1 if left out
+//! ```
+//! The `Webhook` type asks nothing as well, it posts the variables as JSON to a URL and moves on by the result,
+//! setting variables from the response. See the [`webhook`](webhook/index.html) module:
+//! ```txt
+//! 6 // Node type must be 6
+//! https://crm.example.com/leads // URL to post to
+//! 9 // Node to go to on success
+//! 23 // Node to go to on failure
+//! receive TICKET = ticket.id // Optional settings, one per line
+//! ```
+//! The `Command` type runs a local program and moves on by its exit status, for internal operations flows.
+//! It is off unless the flow allows the program and the session turns commands on. See the [`command`](command/index.html) module:
+//! ```txt
+//! 7 // Node type must be 7
+//! ./scripts/open-ticket // Program to run
+//! 9 // Node to go to on exit status 0
+//! 23 // Node to go to otherwise
+//! output TICKET // Optional settings, one per line
+//! ```
 //!
 //! Nodes are added and assigned IDs in the order they appear in the input, beginning with 0.
+//! Execution starts at node 0 unless the flow's header says otherwise.
+//!
+//! The file can begin with header lines saying what the flow is. See the [`metadata`](metadata/index.html) module:
+//! ```txt
+//! @name AdReadiness
+//! @version 1.1
+//! @start 0 // Node to begin at
+//! @entry support:8 // Another, chosen by name
+//! @requires NAME // Variables that must be set before starting
+//! @outputs N0 // Variables set by the time an exit message is reached
+//! ```
+//!
+//! Any node can be preceded by directive lines beginning with `@`:
+//! ```txt
+//! @timeout 60:9 // Jump to node 9 if no answer arrives within 60 seconds
+//! @timeout 60:reprompt // Or show the same prompt again
+//! @exhausted 9 // Go to node 9 once a branching node has no options left to show
+//! @action create_ticket // Tell the host application, see the hooks module
+//! ```
+//!
+//! A branch option can end with a guard, a [condition](condition/index.html) on the variables.
+//! It is only offered while the guard holds, and the options shown are numbered from 1 whatever is hidden:
+//! ```txt
+//! Google Ad ID:4 if !GOOGLE_DONE
+//! ```
+//!
+//! Any prompt, option or exit message can be a message key like `#welcome` instead of text,
+//! looked up in per-locale catalogues kept next to the file. See the [`locale`](locale/index.html) module.
+//!
+//! ## Driving the machine
+//!
+//! [`Nodes::run`](struct.Nodes.html#method.run) drives the machine on the console.
+//! Other front ends use the step API instead: show [`Nodes::prompt`](struct.Nodes.html#method.prompt),
+//! feed the user's reply to [`Nodes::answer`](struct.Nodes.html#method.answer), and call
+//! [`Nodes::check_timeout`](struct.Nodes.html#method.check_timeout) while waiting.
+//!
+//! To serve many users, parse the file once into a [`Flow`](struct.Flow.html) and start a session
+//! of it for each with [`Nodes::from_flow`](struct.Nodes.html#method.from_flow), or let a
+//! [`SessionManager`](manager/struct.SessionManager.html) keep track of them.
+//! [Hooks](hooks/index.html) let the application act on what happens, or steer it.
 //!
 //! The string prompts perform simple variable expansion.
 //! You can refer to any variable previously defined in the file by prefixing it with a `$`, as in the examples.
+//! Variable names start with a capital letter, followed by capital letters, digits and underscores, like `$N7` or `$GOOGLE_DONE`.
 //! If a lookup fails, the name of the variable in the template will be used instead, without the leading `$`.
+//! Written as `${NAME|capitalize}`, a variable can be passed through [filters](filter/index.html).
+//! Text between `{{#if NAME}}` and `{{/if}}` is only shown if `NAME` is set, see the [`condition`](condition/index.html) module.
+//! Branch options are templates too, and the variable for a branching node is set to the option as shown.
 
 #[macro_use]
 extern crate pest_derive;
 
+pub mod analysis;
+pub mod command;
+pub mod condition;
+pub mod export;
+pub mod filter;
+pub mod hooks;
+pub mod locale;
+pub mod manager;
+pub mod metadata;
+pub mod replay;
+pub mod scenario;
+pub mod server;
+pub mod simulate;
+pub mod split;
+pub mod stats;
+mod timeout;
+pub mod transcript;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod validate;
+mod value;
+pub mod webhook;
+
+pub use timeout::{Clock, ManualClock, SystemClock, Timeout, TimeoutAction};
+pub use value::Value;
+
+use command::Command;
+use condition::Condition;
+use export::Outcome;
+use filter::{Call, Filters};
+use hooks::{Decision, HookSet, Hooks};
+use locale::Catalogues;
+use metadata::{EntryPoint, Metadata};
 use pest::{iterators::Pair, Parser};
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use serde::{Deserialize, Serialize};
+use split::SharedRng;
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fmt,
     fs::File,
     io::{self, BufRead, BufReader, Read, Write},
+    path::Path,
+    str::FromStr,
+    sync::{mpsc, Arc},
+    thread,
+    time::{Duration, Instant},
 };
+use timeout::SharedClock;
+use transcript::{Cause, Event, Recorder, TranscriptSink};
+use webhook::{SharedTransport, Transport, Webhook};
 
 /// Trim either \n or \r\n from a String
 fn trim_whitespace(s: &str) -> String {
@@ -71,7 +200,10 @@ fn trim_whitespace(s: &str) -> String {
 /// Unique node identifier type
 // usize is the platform-dependent pointer-sized unsigned integer type
 // e.g. on 64 bit platform this is 8 bytes
-type NodeId = usize;
+pub type NodeId = usize;
+
+/// Variables to set, in order
+pub(crate) type Assignments = Vec<(String, Value)>;
 
 /// Exit condition
 static TERMINATING_NODE: NodeId = 9999;
@@ -79,8 +211,47 @@ static TERMINATING_NODE: NodeId = 9999;
 /// Input file
 static DEFAULT_INPUT_FILE: &str = "input.txt";
 
-/// A single branch option (prompt, destination)
-type BranchOption = (String, NodeId);
+/// A single branch option
+#[derive(Debug, PartialEq)]
+struct BranchOption {
+    /// Option text
+    text: String,
+    /// Node to go to if chosen
+    destination: NodeId,
+    /// Added to scores when chosen, by score name
+    points: Vec<(String, i64)>,
+    /// Only offered while this holds
+    guard: Option<Condition>,
+}
+
+/// A range of scores and where it leads
+#[derive(Debug, PartialEq)]
+struct Band {
+    /// Lowest score included, if there is a lower limit
+    min: Option<i64>,
+    /// Highest score included, if there is an upper limit
+    max: Option<i64>,
+    /// Node to go to
+    destination: NodeId,
+}
+
+/// One arm of a split and how often it's taken
+#[derive(Debug, PartialEq)]
+struct Variant {
+    /// Name recorded for the variant
+    name: String,
+    /// Share of sessions, relative to the other variants
+    weight: u32,
+    /// Node to go to
+    destination: NodeId,
+}
+
+impl Band {
+    /// Whether a score falls in the band
+    fn contains(&self, score: i64) -> bool {
+        self.min.iter().all(|&min| score >= min) && self.max.iter().all(|&max| score <= max)
+    }
+}
 
 /// Each possible node variant
 #[derive(Debug, PartialEq)]
@@ -91,6 +262,14 @@ enum NodeType {
     Question(NodeId, NodeId, Vec<String>),
     // Terminating message text
     Terminating(String),
+    // Score bands, checked in order
+    Routing(Vec<Band>),
+    // Variants, one chosen at random by weight
+    Split(Vec<Variant>),
+    // Where to post and where to go after
+    Webhook(Webhook),
+    // What to run and where to go after
+    Command(Command),
 }
 
 /// Node type
@@ -100,6 +279,12 @@ struct Node {
     node_type: NodeType,
     /// Variable name associated with his node
     variable: Option<String>,
+    /// Overrides the machine-wide timeout for this node
+    timeout: Option<Timeout>,
+    /// Where a branching node goes once every option is hidden by its guard
+    exhausted: Option<NodeId>,
+    /// Actions for the host application when the node is entered
+    actions: Vec<String>,
 }
 
 impl Node {
@@ -108,29 +293,137 @@ impl Node {
         Self {
             node_type,
             variable,
+            timeout: None,
+            exhausted: None,
+            actions: Vec::new(),
+        }
+    }
+}
+
+/// What the current node is asking of the user, templates already resolved
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Prompt {
+    /// Choose one of the numbered options, counting from 1
+    Branching { text: String, options: Vec<String> },
+    /// Free text entry. Each blank answer moves to the next of `attempts` prompts
+    Question {
+        text: String,
+        attempt: usize,
+        attempts: usize,
+    },
+    /// Exit message, any answer ends execution
+    Terminating { text: String },
+}
+
+impl Prompt {
+    /// Prompt text, whatever the kind of node
+    pub fn text(&self) -> &str {
+        match self {
+            Prompt::Branching { text, .. }
+            | Prompt::Question { text, .. }
+            | Prompt::Terminating { text } => text,
+        }
+    }
+}
+
+/// Result of feeding the machine an answer or a timeout
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Step {
+    /// Moved to this node
+    Transition(NodeId),
+    /// Stayed on the current node, show its prompt again
+    Reprompt,
+    /// Execution has ended
+    Finished,
+}
+
+/// An accepted answer, not yet applied
+enum Accepted {
+    /// Store this value, add these points and move to this node
+    Store(String, NodeId, Vec<(String, i64)>),
+    /// Blank answer to a question
+    Blank,
+    /// Dismiss the exit message
+    Dismiss,
+}
+
+/// Rejected input, the machine state is unchanged
+#[derive(Clone, Debug, PartialEq)]
+pub enum InputError {
+    /// Blank answer to a branching node
+    Empty,
+    /// Branching answer that isn't a number
+    Unrecognized(String),
+    /// Branching answer that isn't one of the options
+    OutOfRange(usize),
+    /// The machine has already terminated
+    Finished,
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputError::Empty => write!(f, "Please choose one of the options"),
+            InputError::Unrecognized(e) => write!(f, "Unrecognized input: {}", e),
+            InputError::OutOfRange(_) => write!(f, "Not a valid option!"),
+            InputError::Finished => write!(f, "Execution has already finished"),
         }
     }
 }
 
 /// User-defined variables
-#[derive(Debug, PartialEq)]
-pub struct Env(HashMap<String, String>);
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Env(HashMap<String, Value>);
 
 impl Env {
-    /// Retrieve the value stored at variable_name.
+    /// Read variables from a JSON object, like `{"NAME": "Alice", "AGE": 42}`, keeping their types
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let values: BTreeMap<String, serde_json::Value> =
+            serde_json::from_str(json).map_err(|e| e.to_string())?;
+        Ok(values
+            .into_iter()
+            .map(|(k, v)| (k, Value::from(v)))
+            .collect())
+    }
+    /// Retrieve the value stored at variable_name, as text.
     pub fn get_variable(&self, variable_name: &str) -> String {
         match self.0.get(variable_name) {
-            Some(v) => v.into(),
+            Some(v) => v.to_string(),
             None => variable_name.into(),
         }
     }
-    /// Set variable_name to value, always overwrites
+    /// Value stored at variable_name, if it has been set
+    pub fn get(&self, variable_name: &str) -> Option<&Value> {
+        self.0.get(variable_name)
+    }
+    /// Value stored at variable_name as a whole number, if it is or reads as one
+    pub fn get_int(&self, variable_name: &str) -> Option<i64> {
+        self.get(variable_name)?.as_int()
+    }
+    /// Value stored at variable_name as a number, if it is or reads as one
+    pub fn get_float(&self, variable_name: &str) -> Option<f64> {
+        self.get(variable_name)?.as_float()
+    }
+    /// Value stored at variable_name as a flag, if it is or reads as one
+    pub fn get_bool(&self, variable_name: &str) -> Option<bool> {
+        self.get(variable_name)?.as_bool()
+    }
+    /// Value stored at variable_name as a list, a single value being a list of one
+    pub fn get_list(&self, variable_name: &str) -> Option<Vec<Value>> {
+        self.get(variable_name).map(Value::as_list)
+    }
+    /// Every variable that has been set, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.0.iter()
+    }
+    /// Set variable_name to text, always overwrites
     pub fn set_variable(&mut self, variable_name: &str, value: String) {
-        let current = self
-            .0
-            .entry(variable_name.into())
-            .or_insert_with(|| variable_name.into());
-        *current = value;
+        self.set_value(variable_name, value);
+    }
+    /// Set variable_name to a value of any type, always overwrites
+    pub fn set_value(&mut self, variable_name: &str, value: impl Into<Value>) {
+        self.0.insert(variable_name.into(), value.into());
     }
     /// Resolve a string template
     /// # Example
@@ -157,39 +450,192 @@ impl Env {
     /// # assert_eq!(env.resolve_template(template), expected.to_string());
     /// ```
     ///
-    // TODO broken if a variable is at the end of a string, you're being dumb somewhere
+    /// A variable can end the template:
+    ///
+    /// ```
+    /// # use winnow_sm::Env;
+    /// # let mut env = Env::default();
+    /// # env.set_variable("NAME", "Al Gore".into());
+    /// let template = "Goodbye, $NAME";
+    /// let expected = "Goodbye, Al Gore";
+    /// # assert_eq!(env.resolve_template(template), expected.to_string());
+    /// ```
     pub fn resolve_template(&self, template: &str) -> String {
-        // Init return string
+        self.resolve_template_in(template, locale::DEFAULT_LOCALE)
+    }
+    /// Resolve a string template, formatting numbers the way `locale` writes them
+    /// # Example
+    ///
+    /// ```
+    /// # use winnow_sm::Env;
+    /// # let mut env = Env::default();
+    /// env.set_value("BUDGET", 25000);
+    /// let template = "Presupuesto: $BUDGET";
+    /// let expected = "Presupuesto: 25.000";
+    /// # assert_eq!(env.resolve_template_in(template, "es"), expected.to_string());
+    /// ```
+    pub fn resolve_template_in(&self, template: &str, locale: &str) -> String {
+        self.resolve_template_with(template, locale, &Filters::default())
+    }
+    /// Resolve a string template, with `filters` available to `${...}` variables
+    /// # Example
+    ///
+    /// ```
+    /// # use winnow_sm::{filter::Filters, Env};
+    /// # let mut env = Env::default();
+    /// env.set_variable("NAME", "  arthur ".into());
+    /// let mut filters = Filters::default();
+    /// filters.register("royal", |text, _| format!("King {}", text));
+    /// let template = "${NAME|trim|capitalize|royal}, hello ${FRIEND|default:\"friend\"}";
+    /// let expected = "King Arthur, hello friend";
+    /// # assert_eq!(env.resolve_template_with(template, "en", &filters), expected.to_string());
+    /// ```
+    pub fn resolve_template_with(&self, template: &str, locale: &str, filters: &Filters) -> String {
+        let template = condition::choose(template, self);
         let mut ret = String::new();
-        // Operate over characters as u8 byte array
-        let vec = template.as_bytes().to_owned();
-        let mut i = 0;
-        while i < vec.len() {
-            if vec[i] == b'$' {
-                // we hit a variable
-                // find the rest of the word
-                let mut var_name = String::new();
-                // Skip the $
-                i += 1;
-                // Read until encounter a non-capital letter or end of vec
-                while vec[i] >= b'A' && vec[i] <= b'Z' && i < vec.len() - 1 {
-                    var_name.push(vec[i] as char);
-                    i += 1;
+        for segment in template_segments(&template) {
+            match segment {
+                Segment::Text(text) => ret.push_str(text),
+                Segment::Variable(name, calls) => {
+                    let mut text = self.get(name).map(|v| v.format(locale));
+                    for call in calls {
+                        let arg = call.arg.as_deref();
+                        text = match text {
+                            None if call.name == "default" => Some(arg.unwrap_or_default().into()),
+                            // Unknown filters leave the text alone
+                            Some(t) => Some(filters.apply(call.name, &t, arg).unwrap_or(t)),
+                            None => None,
+                        };
+                    }
+                    ret.push_str(text.as_deref().unwrap_or(name));
                 }
-                ret.push_str(&self.get_variable(&var_name));
-            } else {
-                ret.push(vec[i] as char);
-                i += 1;
             }
         }
         ret
     }
+    /// Variables a template shows that haven't been set and have no default.
+    /// Those in `{{#if}}` blocks the variables leave hidden don't count.
+    pub fn unresolved(&self, template: &str) -> Vec<String> {
+        required_variables(&condition::choose(template, self))
+            .into_iter()
+            .filter(|v| !self.0.contains_key(v))
+            .collect()
+    }
+}
+
+impl std::iter::FromIterator<(String, String)> for Env {
+    fn from_iter<I: IntoIterator<Item = (String, String)>>(iter: I) -> Self {
+        Self(iter.into_iter().map(|(k, v)| (k, Value::from(v))).collect())
+    }
+}
+
+impl std::iter::FromIterator<(String, Value)> for Env {
+    fn from_iter<I: IntoIterator<Item = (String, Value)>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+/// Piece of a string template
+#[derive(Debug, PartialEq)]
+enum Segment<'a> {
+    /// Literal text
+    Text(&'a str),
+    /// Name following a `$`, without it, and any filters from a `${...}`
+    Variable(&'a str, Vec<Call<'a>>),
+}
+
+/// Length of the variable name `s` begins with, 0 if it doesn't begin with one.
+/// A name is a capital letter followed by any capital letters, digits and underscores, like `N7` or `GOOGLE_DONE`,
+/// the same in templates, conditions and flow files.
+pub(crate) fn variable_name_len(s: &str) -> usize {
+    if !s.starts_with(|c: char| c.is_ascii_uppercase()) {
+        return 0;
+    }
+    s.find(|c: char| !(c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_'))
+        .unwrap_or(s.len())
+}
+
+/// Whether all of `name` is a variable name
+pub(crate) fn is_variable_name(name: &str) -> bool {
+    !name.is_empty() && variable_name_len(name) == name.len()
+}
+
+/// Split a template into literal text and variable references.
+/// A variable is the name following a `$`, or the name inside `${...}`.
+fn template_segments(template: &str) -> Vec<Segment<'_>> {
+    let mut ret = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('$') {
+        if start > 0 {
+            ret.push(Segment::Text(&rest[..start]));
+        }
+        let after = &rest[start + 1..];
+        if let Some(inner) = after.strip_prefix('{') {
+            if let Some(len) = filter::expression_len(inner) {
+                let (name, calls) = filter::parse_expression(&inner[..len]);
+                if is_variable_name(name) {
+                    ret.push(Segment::Variable(name, calls));
+                } else {
+                    // Not a variable, show it as written
+                    ret.push(Segment::Text(&rest[start..start + len + 3]));
+                }
+                rest = &inner[len + 1..];
+                continue;
+            }
+        }
+        // Skip the $, read to the end of the name
+        let len = variable_name_len(after);
+        ret.push(Segment::Variable(&after[..len], Vec::new()));
+        rest = &after[len..];
+    }
+    if !rest.is_empty() {
+        ret.push(Segment::Text(rest));
+    }
+    ret
+}
+
+/// Names of the variables a template refers to, in order of appearance
+pub fn template_variables(template: &str) -> Vec<String> {
+    let mut ret: Vec<String> = Vec::new();
+    for segment in template_segments(template) {
+        if let Segment::Variable(name, _) = segment {
+            if !name.is_empty() && !ret.iter().any(|v| v == name) {
+                ret.push(name.into());
+            }
+        }
+    }
+    ret
+}
+
+/// Variables a template refers to without a `default` filter, in order of appearance
+pub(crate) fn required_variables(template: &str) -> Vec<String> {
+    let mut ret: Vec<String> = Vec::new();
+    for segment in template_segments(template) {
+        if let Segment::Variable(name, calls) = segment {
+            if !name.is_empty()
+                && !calls.iter().any(|c| c.name == "default")
+                && !ret.iter().any(|v| v == name)
+            {
+                ret.push(name.into());
+            }
+        }
+    }
+    ret
 }
 
-impl Default for Env {
-    fn default() -> Self {
-        Self(HashMap::new())
+/// Names of the filters a template uses, in order of appearance
+pub(crate) fn template_filters(template: &str) -> Vec<String> {
+    let mut ret: Vec<String> = Vec::new();
+    for segment in template_segments(template) {
+        if let Segment::Variable(_, calls) = segment {
+            for call in calls {
+                if !ret.iter().any(|f| f == call.name) {
+                    ret.push(call.name.into());
+                }
+            }
+        }
     }
+    ret
 }
 
 #[derive(Parser)]
@@ -205,13 +651,38 @@ fn parse_branch_option_line(parsed: Pair<Rule>) -> BranchOption {
             // skip delimiter
             inner.next();
             let destination = inner.next().unwrap();
-            (
-                prompt.as_str().into(),
-                destination
+            let mut points = Vec::new();
+            let mut guard = None;
+            for pair in inner {
+                match pair.as_rule() {
+                    Rule::points => {
+                        let mut inner = pair.into_inner();
+                        let value = inner.next().unwrap().as_str().parse::<i64>().unwrap();
+                        points.push((inner.next().unwrap().as_str().into(), value));
+                    }
+                    // already checked when parsing the flow
+                    Rule::guard => {
+                        guard = Some(
+                            pair.into_inner()
+                                .next()
+                                .unwrap()
+                                .as_str()
+                                .parse::<Condition>()
+                                .unwrap_or_else(|e| panic!("Fail to parse guard: {}", e)),
+                        )
+                    }
+                    _ => {}
+                }
+            }
+            BranchOption {
+                text: prompt.as_str().into(),
+                destination: destination
                     .as_str()
                     .parse::<usize>()
-                    .expect(&format!("Fail to parse {}", destination.as_str())),
-            )
+                    .unwrap_or_else(|_| panic!("Fail to parse {}", destination.as_str())),
+                points,
+                guard,
+            }
         }
         _ => panic!("Called parse_branch_option_line on the wrong rule"),
     }
@@ -239,128 +710,281 @@ fn parse_int_line(parsed: Pair<Rule>) -> usize {
     }
 }
 
-/// Containing structure for all nodes
-/// Nodes are registered in sequential order
+/// helper function to parse timeout rule
+fn parse_timeout_line(parsed: Pair<Rule>) -> Timeout {
+    match parsed.as_rule() {
+        Rule::timeout => {
+            let mut inner = parsed.into_inner();
+            let secs = inner.next().unwrap().as_str().parse::<u64>().unwrap();
+            // skip delimiter
+            inner.next();
+            let action = match inner.next().unwrap().as_str() {
+                "reprompt" => TimeoutAction::Reprompt,
+                node => TimeoutAction::Goto(node.parse::<usize>().unwrap()),
+            };
+            Timeout::new(Duration::from_secs(secs), action)
+        }
+        _ => panic!("Called parse_timeout_line on the wrong rule"),
+    }
+}
+
+/// helper function to parse band rule
+fn parse_band_line(parsed: Pair<Rule>) -> Band {
+    match parsed.as_rule() {
+        Rule::band => {
+            let mut inner = parsed.into_inner();
+            let range = inner.next().unwrap();
+            let star = range.as_str() == "*";
+            let plus = range.as_str().ends_with('+');
+            let mut bounds = range
+                .into_inner()
+                .map(|b| b.as_str().parse::<i64>().unwrap());
+            let (min, max) = if star {
+                (None, None)
+            } else if plus {
+                (bounds.next(), None)
+            } else {
+                (bounds.next(), bounds.next())
+            };
+            // skip delimiter
+            inner.next();
+            Band {
+                min,
+                max,
+                destination: inner.next().unwrap().as_str().parse::<usize>().unwrap(),
+            }
+        }
+        _ => panic!("Called parse_band_line on the wrong rule"),
+    }
+}
+
+/// helper function to parse variant rule
+fn parse_variant_line(parsed: Pair<Rule>) -> Variant {
+    match parsed.as_rule() {
+        Rule::variant => {
+            let mut inner = parsed.into_inner();
+            let name = inner.next().unwrap().as_str();
+            // skip delimiter
+            inner.next();
+            let destination = inner.next().unwrap().as_str().parse::<usize>().unwrap();
+            let weight = inner
+                .find(|p| p.as_rule() == Rule::weight)
+                .map(|w| {
+                    let w = w.into_inner().next().unwrap().as_str();
+                    w.parse::<u32>()
+                        .unwrap_or_else(|_| panic!("Fail to parse weight {}", w))
+                })
+                .unwrap_or(1);
+            Variant {
+                name: name.into(),
+                weight,
+                destination,
+            }
+        }
+        _ => panic!("Called parse_variant_line on the wrong rule"),
+    }
+}
+
+/// helper function to parse command rule
+fn parse_command(parsed: Pair<Rule>) -> Command {
+    match parsed.as_rule() {
+        Rule::command => {
+            // skip type
+            let mut inner = parsed.into_inner().skip(1);
+            let program = inner.next().unwrap().into_inner().next().unwrap().as_str();
+            let success = parse_int_line(inner.next().unwrap());
+            let failure = parse_int_line(inner.next().unwrap());
+            let mut ret = Command::new(program, success, failure);
+            for setting in inner {
+                let setting = setting.into_inner().next().unwrap();
+                let rule = setting.as_rule();
+                let values = setting.into_inner().map(|p| p.as_str());
+                match rule {
+                    Rule::args => ret.args.extend(values.map(|a| {
+                        a.strip_prefix('"')
+                            .and_then(|a| a.strip_suffix('"'))
+                            .unwrap_or(a)
+                            .to_string()
+                    })),
+                    Rule::pass_env => ret.env.extend(values.map(String::from)),
+                    Rule::output => ret.output = values.last().map(String::from),
+                    _ => unreachable!(),
+                }
+            }
+            ret
+        }
+        _ => panic!("Called parse_command on the wrong rule"),
+    }
+}
+
+/// helper function to parse webhook rule
+fn parse_webhook(parsed: Pair<Rule>) -> Webhook {
+    match parsed.as_rule() {
+        Rule::webhook => {
+            // skip type
+            let mut inner = parsed.into_inner().skip(1);
+            let url = inner.next().unwrap().into_inner().next().unwrap().as_str();
+            let success = parse_int_line(inner.next().unwrap());
+            let failure = parse_int_line(inner.next().unwrap());
+            let mut ret = Webhook::new(url, success, failure);
+            for setting in inner {
+                let setting = setting.into_inner().next().unwrap();
+                let rule = setting.as_rule();
+                let mut values = setting.into_inner().map(|p| p.as_str());
+                match rule {
+                    Rule::send => ret.send.extend(values.map(String::from)),
+                    Rule::receive => {
+                        let name = values.next().unwrap();
+                        ret.receive
+                            .push((name.into(), values.next().unwrap().into()));
+                    }
+                    Rule::retries => ret.retries = values.next().unwrap().parse().unwrap(),
+                    Rule::wait => {
+                        ret.timeout = Duration::from_secs(values.next().unwrap().parse().unwrap())
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            ret
+        }
+        _ => panic!("Called parse_webhook on the wrong rule"),
+    }
+}
+
+/// helper function to parse exhausted rule
+fn parse_exhausted_line(parsed: Pair<Rule>) -> NodeId {
+    match parsed.as_rule() {
+        Rule::exhausted => parsed
+            .into_inner()
+            .next()
+            .unwrap()
+            .as_str()
+            .parse::<usize>()
+            .unwrap(),
+        _ => panic!("Called parse_exhausted_line on the wrong rule"),
+    }
+}
+
+/// A parsed input file: the nodes and nothing about who is running them.
+/// Wrap it in an `Arc` to start any number of sessions without parsing the file again.
 #[derive(Debug, Default, PartialEq)]
-pub struct Nodes {
-    /// Current node tracker
-    current_node: NodeId,
-    /// User-defined mapping of names to values
-    env: Env,
-    /// Internal state tracker
-    internal_state: NodeId,
+pub struct Flow {
     /// Array (actually variable-sized heap-allocated vector) of nodes
     nodes: Vec<Node>,
+    /// Texts for message keys, by locale
+    catalogues: Catalogues,
+    /// Filters templates can use
+    filters: Filters,
+    /// Programs command nodes may run
+    allowed: Vec<String>,
+    /// What the header says about the flow
+    metadata: Metadata,
 }
 
-impl Nodes {
-    /// Constructor will parse specified file or `input.txt` if None
-    pub fn new(specified_input: Option<String>) -> Self {
-        let input_file = specified_input.unwrap_or_else(|| DEFAULT_INPUT_FILE.into());
-        println!("Input file: {}\n", input_file);
-        let mut ret = Nodes::default();
+impl Flow {
+    /// Parse the specified file and any catalogues next to it, reporting what went wrong
+    pub fn from_file(input_file: &str) -> Result<Self, String> {
         // read input file
         let mut file_str = String::new();
-        let f = File::open(input_file).expect("Should open input file");
+        let f = File::open(input_file).map_err(|e| format!("{}: {}", input_file, e))?;
         let mut bfr = BufReader::new(f);
         bfr.read_to_string(&mut file_str)
-            .expect("Should read input file");
-        let mut parsed = match NodesParser::parse(Rule::nodes, &file_str) {
-            Ok(parse_tree) => parse_tree,
-            Err(e) => panic!(format!("{}", e)),
-        };
-        ret.read_and_register(parsed.next().unwrap());
-        ret
+            .map_err(|e| format!("{}: {}", input_file, e))?;
+        let mut ret: Self = file_str
+            .parse()
+            .map_err(|e| format!("{}:\n{}", input_file, e))?;
+        ret.catalogues = Catalogues::load(Path::new(input_file))?;
+        Ok(ret)
     }
 
-    /// Execute machine
-    pub fn run(&mut self) {
-        while self.current_node != TERMINATING_NODE {
-            use NodeType::*;
-            let stdin = io::stdin();
-            let mut stdout = io::stdout();
-            match &self.nodes[self.current_node].node_type {
-                Question(success, fail, qs) => {
-                    // if we haven't run out of prompts
-                    if self.internal_state < qs.len() {
-                        // TODO Stand-in!
-                        // Display prompt
-                        print!("{}", self);
-                        stdout.flush().expect("Should flush stdout");
-                        // Get string
-                        let mut line = String::new();
-                        stdin.lock().read_line(&mut line).unwrap();
-                        // Truncate newline
-                        line = trim_whitespace(&line);
-                        match line.len() {
-                            0 => {
-                                // Empty input - just a newline
-                                self.internal_state += 1;
-                            }
-                            _ => {
-                                // Store anything else
-                                // Unwraps are safe - we already know its a Question
-                                // Set value
-                                self.env.set_variable(
-                                    &self.nodes[self.current_node].variable.as_ref().unwrap(),
-                                    line,
-                                );
-                                let destination = *success;
-                                self.state_transition(destination);
-                            }
-                        }
-                    } else {
-                        // Too many blanks!
-                        let destination = *fail;
-                        self.state_transition(destination);
-                    }
+    /// Number of nodes
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Whether there are no nodes at all
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// What the flow's header declares, empty if it has none
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Programs the flow's `@allow` lines let command nodes run
+    pub fn allowed_programs(&self) -> &[String] {
+        &self.allowed
+    }
+
+    /// Every variable a node sets, scores included, in the order the nodes appear
+    pub fn variables(&self) -> Vec<String> {
+        let mut ret: Vec<String> = Vec::new();
+        for node in &self.nodes {
+            let scores = match &node.node_type {
+                NodeType::Branching(_, options) => options
+                    .iter()
+                    .flat_map(|o| o.points.iter().map(|(name, _)| name))
+                    .collect(),
+                NodeType::Webhook(webhook) => {
+                    webhook.receive.iter().map(|(name, _)| name).collect()
                 }
-                Branching(_, options) => {
-                    // TODO stand-in!
-                    // Display prompt
-                    print!("{}", self);
-                    stdout.flush().expect("Should flush stdout");
-                    // Get string
-                    let mut line = String::new();
-                    stdin.lock().read_line(&mut line).unwrap();
-                    // truncate newline
-                    line = trim_whitespace(&line);
-                    match line.len() {
-                        0 => {
-                            eprintln!("TODO - The graphical option won't allow empty input, so just comply please")
-                        },
-                        _ => {
-                            // Check if its a valid option, if so, return options[choice-1], if not, yell
-                            let choice = line.as_str().parse::<usize>();
-                            match choice {
-                                Ok(n) => {
-                                    if n > options.len() {
-                                        eprintln!("Not a valid option!");
-                                    } else {
-                                        // Set variable to option text
-                                        self.env.set_variable(&self.nodes[self.current_node].variable.as_ref().unwrap(), options[n - 1].0.clone());
-                                        // Transition to destination
-                                        let destination = options[n - 1].1;
-                                        self.state_transition(destination);
-                                    }
-                                }
-                                Err(e) => eprintln!("Unrecognized input: {}", e),
-                            }
-                        },
-                    }
+                NodeType::Command(command) => command.output.iter().collect(),
+                _ => Vec::new(),
+            };
+            for variable in node.variable.iter().chain(scores) {
+                if !ret.contains(variable) {
+                    ret.push(variable.clone());
                 }
-                Terminating(_) => {
-                    // TODO stand-in!
-                    // Display
-                    println!("{}", self);
-                    // Wait for exit
-                    let _ = stdin.lock().lines().next();
-                    self.state_transition(TERMINATING_NODE);
+            }
+        }
+        ret
+    }
+
+    /// Every score branch options add points to, in the order they appear
+    pub fn scores(&self) -> Vec<String> {
+        let mut ret: Vec<String> = Vec::new();
+        for node in &self.nodes {
+            if let NodeType::Branching(_, options) = &node.node_type {
+                for (name, _) in options.iter().flat_map(|o| &o.points) {
+                    if !ret.contains(name) {
+                        ret.push(name.clone());
+                    }
                 }
             }
-            // Print padding line
-            println!();
         }
+        ret
+    }
+
+    /// Every action nodes name for the host application, in the order they appear
+    pub fn actions(&self) -> Vec<String> {
+        let mut ret: Vec<String> = Vec::new();
+        for action in self.nodes.iter().flat_map(|n| &n.actions) {
+            if !ret.contains(action) {
+                ret.push(action.clone());
+            }
+        }
+        ret
+    }
+
+    /// Texts for message keys, by locale
+    pub fn catalogues(&self) -> &Catalogues {
+        &self.catalogues
+    }
+
+    /// Add or change catalogues
+    pub fn catalogues_mut(&mut self) -> &mut Catalogues {
+        &mut self.catalogues
+    }
+
+    /// Filters templates can use
+    pub fn filters(&self) -> &Filters {
+        &self.filters
+    }
+
+    /// Add custom filters
+    pub fn filters_mut(&mut self) -> &mut Filters {
+        &mut self.filters
     }
 
     /// Add a question node to the set
@@ -390,6 +1014,20 @@ impl Nodes {
         ));
     }
 
+    /// Add a routing node to the set
+    fn register_routing_node(&mut self, score: &str, bands: Vec<Band>) {
+        self.nodes
+            .push(Node::new(NodeType::Routing(bands), Some(score.into())));
+    }
+
+    /// Add a split node to the set
+    fn register_split_node(&mut self, variable_name: &str, variants: Vec<Variant>) {
+        self.nodes.push(Node::new(
+            NodeType::Split(variants),
+            Some(variable_name.into()),
+        ));
+    }
+
     /// Add a terminating node to the set
     fn register_terminating_node(&mut self, text: &str) {
         self.nodes
@@ -405,7 +1043,33 @@ impl Nodes {
                     self.read_and_register(child);
                 }
             }
-            Rule::node => self.read_and_register(parsed.into_inner().next().unwrap()),
+            Rule::node => {
+                // zero or more directives, then the node itself
+                let mut timeout = None;
+                let mut exhausted = None;
+                let mut actions = Vec::new();
+                for child in parsed.into_inner() {
+                    match child.as_rule() {
+                        Rule::directive => {
+                            let directive = child.into_inner().next().unwrap();
+                            match directive.as_rule() {
+                                Rule::timeout => timeout = Some(parse_timeout_line(directive)),
+                                Rule::exhausted => {
+                                    exhausted = Some(parse_exhausted_line(directive))
+                                }
+                                Rule::action => actions
+                                    .push(directive.into_inner().next().unwrap().as_str().into()),
+                                _ => unreachable!(),
+                            }
+                        }
+                        _ => self.read_and_register(child),
+                    }
+                }
+                let node = self.nodes.last_mut().unwrap();
+                node.timeout = timeout;
+                node.exhausted = exhausted;
+                node.actions = actions;
+            }
             Rule::question => {
                 // skip type
                 let mut inner = parsed.into_inner().skip(1);
@@ -436,68 +1100,1518 @@ impl Nodes {
                 }
                 self.register_branching_node(&var_name, &question, options);
             }
-            Rule::terminating => {
+            Rule::routing => {
                 // skip type
                 let mut inner = parsed.into_inner().skip(1);
-                // Terminate message
+                // score to route by
+                let score = parse_string_line(inner.next().unwrap());
+                // bands
+                let bands = inner.map(parse_band_line).collect();
+                self.register_routing_node(&score, bands);
+            }
+            Rule::split => {
+                // skip type
+                let mut inner = parsed.into_inner().skip(1);
+                // variable to record the variant in
+                let var_name = parse_string_line(inner.next().unwrap());
+                // variants
+                let variants = inner.map(parse_variant_line).collect();
+                self.register_split_node(&var_name, variants);
+            }
+            Rule::header => {
+                let header = parsed.into_inner().next().unwrap();
+                let rule = header.as_rule();
+                let mut values = header
+                    .into_inner()
+                    .filter(|p| p.as_rule() != Rule::newline)
+                    .map(|p| p.as_str().to_string());
+                let metadata = &mut self.metadata;
+                match rule {
+                    Rule::allow => self.allowed.extend(values),
+                    Rule::flow_name => metadata.name = values.next(),
+                    Rule::flow_version => metadata.version = values.next(),
+                    Rule::flow_author => metadata.author = values.next(),
+                    Rule::start => metadata.start = values.next().unwrap().parse().unwrap(),
+                    Rule::entry => {
+                        let name = values.next().unwrap();
+                        // skip delimiter
+                        let node = values.nth(1).unwrap().parse().unwrap();
+                        let requires = values.collect();
+                        metadata.entries.insert(name, EntryPoint { node, requires });
+                    }
+                    Rule::requires => metadata.requires.extend(values),
+                    Rule::outputs => metadata.outputs.extend(values),
+                    _ => unreachable!(),
+                }
+            }
+            Rule::command => {
+                self.nodes
+                    .push(Node::new(NodeType::Command(parse_command(parsed)), None));
+            }
+            Rule::webhook => {
+                self.nodes
+                    .push(Node::new(NodeType::Webhook(parse_webhook(parsed)), None));
+            }
+            Rule::terminating => {
+                // skip type
+                let mut inner = parsed.into_inner().skip(1);
+                // Terminate message
                 let message = parse_string_line(inner.next().unwrap());
                 self.register_terminating_node(&message);
             }
             Rule::EOI => {}
-            _ => panic!(format!("Cannot handle {:?}", parsed.as_rule())),
+            _ => panic!("Cannot handle {:?}", parsed.as_rule()),
+        }
+    }
+}
+
+/// One user's progress through a shared [`Flow`](struct.Flow.html).
+/// This is the same type as [`Nodes`](struct.Nodes.html), named for code juggling many of them.
+/// An alias is enough because `Nodes` already keeps the parsed nodes, catalogues and metadata behind an
+/// `Arc<Flow>`: the rest of it is the user's own variables, position, path and settings,
+/// so a session costs no more than that however big the flow.
+pub type Session = Nodes;
+
+/// Where a session has got to, enough to pick it up again later
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Snapshot {
+    /// Node awaiting input
+    pub current_node: NodeId,
+    /// Prompts already used up at a question node
+    pub internal_state: usize,
+    /// Variables set so far
+    pub variables: BTreeMap<String, Value>,
+    /// Nodes entered so far
+    pub path: Vec<NodeId>,
+    /// Chosen locale, if any
+    #[serde(default)]
+    pub locale: Option<String>,
+}
+
+/// Containing structure for all nodes
+/// Nodes are registered in sequential order
+#[derive(Debug, Default, PartialEq)]
+pub struct Nodes {
+    /// Current node tracker
+    current_node: NodeId,
+    /// User-defined mapping of names to values
+    env: Env,
+    /// Internal state tracker
+    internal_state: NodeId,
+    /// The flow being run, shared with any other sessions of it
+    flow: Arc<Flow>,
+    /// Timeout for nodes that don't specify their own
+    timeout: Option<Timeout>,
+    /// Time source for timeouts
+    clock: SharedClock,
+    /// When the current prompt was first shown
+    prompted_at: Option<Instant>,
+    /// Nodes entered so far, in order
+    path: Vec<NodeId>,
+    /// Where to record what happens, if anywhere
+    transcript: Recorder,
+    /// Locale for message keys, or the flow's default if `None`
+    locale: Option<String>,
+    /// Pass straight through questions whose variable is already set
+    skip_answered: bool,
+    /// Randomness for split nodes
+    rng: SharedRng,
+    /// Whether command nodes may run their programs
+    commands_enabled: bool,
+    /// Told what happens, if anything is listening
+    hooks: HookSet,
+    /// Makes webhook posts
+    transport: SharedTransport,
+}
+
+impl Nodes {
+    /// Constructor will parse specified file or `input.txt` if None
+    pub fn new(specified_input: Option<String>) -> Self {
+        let input_file = specified_input.unwrap_or_else(|| DEFAULT_INPUT_FILE.into());
+        println!("Input file: {}\n", input_file);
+        match Self::from_file(&input_file) {
+            Ok(nodes) => nodes,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Parse the specified file, reporting what went wrong instead of panicking
+    pub fn from_file(input_file: &str) -> Result<Self, String> {
+        Flow::from_file(input_file).map(|flow| Self::from_flow(Arc::new(flow)))
+    }
+
+    /// Start a session of an already parsed flow with nothing set.
+    /// Doesn't check the flow's `@requires`, use [`from_flow_with`](#method.from_flow_with) or
+    /// [`from_flow_at`](#method.from_flow_at) for that
+    pub fn from_flow(flow: Arc<Flow>) -> Self {
+        Self {
+            current_node: flow.metadata.start,
+            flow,
+            ..Default::default()
+        }
+    }
+
+    /// Start a session of an already parsed flow with `env` holding at least the variables the flow requires
+    pub fn from_flow_with(flow: Arc<Flow>, env: Env) -> Result<Self, String> {
+        let missing = flow.metadata.missing(&env, None);
+        match missing.is_empty() {
+            true => Ok(Self::from_flow(flow).with_env(env)),
+            false => Err(format!("This flow needs {} set first", missing.join(", "))),
         }
     }
 
-    /// State transition
-    fn state_transition(&mut self, new_state: NodeId) {
+    /// Start a session of an already parsed flow at one of its named entry points instead of its start,
+    /// with `env` holding at least the variables the entry point and the flow require
+    pub fn from_flow_at(flow: Arc<Flow>, entry: &str, env: Env) -> Result<Self, String> {
+        match flow.metadata.entries.get(entry) {
+            Some(point) => {
+                let missing = flow.metadata.missing(&env, Some(entry));
+                if !missing.is_empty() {
+                    return Err(format!(
+                        "Entry point {} needs {} set first",
+                        entry,
+                        missing.join(", ")
+                    ));
+                }
+                Ok(Self {
+                    current_node: point.node,
+                    ..Self::from_flow(flow).with_env(env)
+                })
+            }
+            None => {
+                let names: Vec<&str> = flow.metadata.entries.keys().map(String::as_str).collect();
+                Err(match names.len() {
+                    0 => format!("No entry point {}, the flow declares none", entry),
+                    _ => format!(
+                        "No entry point {}, choose from: {}",
+                        entry,
+                        names.join(", ")
+                    ),
+                })
+            }
+        }
+    }
+
+    /// Start with variables already set, such as ones known before the session began.
+    /// Like [`from_flow`](#method.from_flow), doesn't check `@requires`
+    pub fn with_env(mut self, env: Env) -> Self {
+        self.env = env;
+        self
+    }
+
+    /// Whether to pass straight through questions whose variable is already set,
+    /// taking their answered destination instead of asking again
+    pub fn set_skip_answered(&mut self, skip: bool) {
+        self.skip_answered = skip;
+    }
+
+    /// Whether command nodes may run their programs. Off unless turned on, and needs the `command` feature
+    pub fn set_commands_enabled(&mut self, enabled: bool) {
+        self.commands_enabled = enabled;
+    }
+
+    /// Replace what webhook nodes post through, such as a
+    /// [`StubTransport`](webhook/struct.StubTransport.html) that doesn't post anything
+    pub fn set_webhook_transport(&mut self, transport: Arc<dyn Transport>) {
+        self.transport = SharedTransport(transport);
+    }
+
+    /// What the flow's header declares, empty if it has none
+    pub fn metadata(&self) -> &Metadata {
+        &self.flow.metadata
+    }
+
+    /// The flow this session is running
+    pub fn flow(&self) -> &Arc<Flow> {
+        &self.flow
+    }
+
+    /// Execute machine on the console, reading answers from stdin
+    pub fn run(&mut self) {
+        // Read stdin on its own thread so a pending prompt can time out
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let stdin = io::stdin();
+            for line in stdin.lock().lines() {
+                match line {
+                    Ok(l) => {
+                        if tx.send(l).is_err() {
+                            break;
+                        }
+                    }
+                    Err(_) => break,
+                }
+            }
+        });
+        let mut stdout = io::stdout();
+        while !self.is_finished() {
+            // Display prompt
+            match self.prompt() {
+                p @ Prompt::Terminating { .. } => println!("{}", p),
+                p => print!("{}", p),
+            }
+            stdout.flush().expect("Should flush stdout");
+            // Wait for an answer, or as long as the timeout allows
+            let received = match self.time_remaining() {
+                Some(remaining) => rx.recv_timeout(remaining),
+                None => rx.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+            };
+            match received {
+                Ok(line) => {
+                    if let Err(e) = self.answer(&trim_whitespace(&line)) {
+                        eprintln!("{}", e);
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    println!();
+                    self.check_timeout();
+                }
+                // Input closed, nobody is left to answer
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    println!();
+                    return;
+                }
+            }
+            // Print padding line
+            println!();
+        }
+    }
+
+    /// Whether execution has ended
+    pub fn is_finished(&self) -> bool {
+        self.current_node == TERMINATING_NODE
+    }
+
+    /// Id of the node currently awaiting input
+    pub fn current_node(&self) -> NodeId {
+        self.current_node
+    }
+
+    /// User-defined variables collected so far
+    pub fn env(&self) -> &Env {
+        &self.env
+    }
+
+    /// The current prompt with templates resolved, without starting its timer
+    pub fn current_prompt(&self) -> Prompt {
+        use NodeType::*;
+        // Routing or a split can end execution before anything is asked
+        if self.is_finished() {
+            return Prompt::Terminating {
+                text: String::new(),
+            };
+        }
+        match &self.flow.nodes[self.current_node].node_type {
+            Branching(question, options) => Prompt::Branching {
+                text: self.resolve(question),
+                options: self
+                    .visible_options(options)
+                    .map(|o| self.resolve(&o.text))
+                    .collect(),
+            },
+            Question(_, _, qs) => Prompt::Question {
+                text: self.resolve(&qs[self.internal_state]),
+                attempt: self.internal_state,
+                attempts: qs.len(),
+            },
+            Terminating(message) => Prompt::Terminating {
+                text: self.resolve(message),
+            },
+            // Only ever current if nodes that ask nothing went round in a loop, so there's nothing to say
+            Routing(_) | Split(_) | Webhook(_) | Command(_) => Prompt::Terminating {
+                text: String::new(),
+            },
+        }
+    }
+
+    /// Unresolved template for the current prompt
+    fn current_template(&self) -> &str {
+        use NodeType::*;
+        if self.is_finished() {
+            return "";
+        }
+        self.text(match &self.flow.nodes[self.current_node].node_type {
+            Branching(question, _) => question,
+            Question(_, _, qs) => &qs[self.internal_state],
+            Terminating(message) => message,
+            Routing(_) | Split(_) | Webhook(_) | Command(_) => "",
+        })
+    }
+
+    /// Text from the flow in this session's locale, templates resolved
+    fn resolve(&self, raw: &str) -> String {
+        self.env
+            .resolve_template_with(self.text(raw), self.locale(), &self.flow.filters)
+    }
+
+    /// Text from the flow in this session's locale
+    fn text<'a>(&'a self, raw: &'a str) -> &'a str {
+        self.flow.catalogues.localise(self.locale.as_deref(), raw)
+    }
+
+    /// Show message keys in `locale`, or the flow's default locale if `None`
+    pub fn set_locale(&mut self, locale: Option<String>) {
+        self.locale = locale;
+    }
+
+    /// Locale message keys are shown in
+    pub fn locale(&self) -> &str {
+        self.locale
+            .as_deref()
+            .unwrap_or_else(|| self.flow.catalogues.default_locale())
+    }
+
+    /// Variables the current prompt refers to that haven't been set.
+    /// These are shown to the user as their bare names.
+    pub fn unresolved_variables(&self) -> Vec<String> {
+        self.env.unresolved(self.current_template())
+    }
+
+    /// The current prompt, marking it as shown to the user.
+    /// Any timeout starts counting from the first call for a given prompt.
+    pub fn prompt(&mut self) -> Prompt {
+        self.enter_start();
+        let prompt = self.current_prompt();
+        if self.prompted_at.is_none() && !self.is_finished() {
+            self.prompted_at = Some(self.clock.0.now());
+            let (text, options) = match &prompt {
+                Prompt::Branching { text, options } => (text.clone(), options.clone()),
+                Prompt::Question { text, .. } | Prompt::Terminating { text } => {
+                    (text.clone(), Vec::new())
+                }
+            };
+            self.transcript.record(Event::Prompted {
+                node: self.current_node,
+                text,
+                options,
+            });
+        }
+        prompt
+    }
+
+    /// Record arriving at the first node, the first time the machine is driven
+    fn enter_start(&mut self) {
+        if self.path.is_empty() && !self.is_finished() {
+            self.enter(self.current_node);
+            self.pass_through_nodes();
+        }
+    }
+
+    /// Record arriving at a node and run its actions
+    fn enter(&mut self, node: NodeId) {
+        self.path.push(node);
+        self.transcript.record(Event::NodeEntered { node });
+        let actions = &self.flow.nodes[node].actions;
+        for name in actions {
+            self.transcript.record(Event::Action {
+                node,
+                name: name.clone(),
+            });
+        }
+        self.hooks.enter(node, actions, &self.env);
+    }
+
+    /// Options of a branching node whose guards hold, in order
+    fn visible_options<'a>(
+        &'a self,
+        options: &'a [BranchOption],
+    ) -> impl Iterator<Item = &'a BranchOption> {
+        options
+            .iter()
+            .filter(move |o| o.guard.iter().all(|g| g.holds(&self.env)))
+    }
+
+    /// Where to go from the current node without asking anything, why, and any variables to set on the way:
+    /// a question to skip, a branching node with every option hidden, or a routing, split, webhook or command node
+    fn pass_through(&mut self) -> Option<(NodeId, Cause, Assignments)> {
+        if self.is_finished() {
+            return None;
+        }
+        let node = &self.flow.nodes[self.current_node];
+        match (&node.node_type, &node.variable) {
+            (NodeType::Question(success, _, _), Some(v))
+                if self.skip_answered && self.env.get(v).is_some() =>
+            {
+                Some((*success, Cause::Skipped, Vec::new()))
+            }
+            (NodeType::Routing(bands), Some(score)) => {
+                let score = self.env.get_int(score).unwrap_or_default();
+                let destination = bands
+                    .iter()
+                    .find(|b| b.contains(score))
+                    .map_or(TERMINATING_NODE, |b| b.destination);
+                Some((destination, Cause::Scored, Vec::new()))
+            }
+            (NodeType::Split(variants), Some(variable)) => {
+                Some(match self.choose_variant(variants, variable) {
+                    Some(v) => (
+                        v.destination,
+                        Cause::Split,
+                        vec![(variable.clone(), v.name.as_str().into())],
+                    ),
+                    None => (TERMINATING_NODE, Cause::Split, Vec::new()),
+                })
+            }
+            (NodeType::Branching(_, options), _)
+                if self.visible_options(options).next().is_none() =>
+            {
+                node.exhausted.map(|to| (to, Cause::Exhausted, Vec::new()))
+            }
+            (NodeType::Command(command), _) => {
+                let result = if self.commands_enabled {
+                    let args: Vec<String> = command.args.iter().map(|a| self.resolve(a)).collect();
+                    command.run(&args, &self.env)
+                } else {
+                    Err(format!("{}: commands aren't enabled", command.program))
+                };
+                Some(match result {
+                    Ok(output) => (command.success, Cause::Succeeded, output),
+                    Err(reason) => {
+                        self.transcript.record(Event::Failed {
+                            node: self.current_node,
+                            reason,
+                        });
+                        (command.failure, Cause::Failed, Vec::new())
+                    }
+                })
+            }
+            (NodeType::Webhook(webhook), _) => Some(
+                match self
+                    .transport
+                    .0
+                    .send(&webhook.request(self.current_node, &self.env))
+                {
+                    Ok(received) => (webhook.success, Cause::Succeeded, received),
+                    Err(reason) => {
+                        self.transcript.record(Event::Failed {
+                            node: self.current_node,
+                            reason,
+                        });
+                        (webhook.failure, Cause::Failed, Vec::new())
+                    }
+                },
+            ),
+            _ => None,
+        }
+    }
+
+    /// The variant a split node takes: the one already recorded in its variable if there is one,
+    /// otherwise one drawn at random by weight. `None` if every weight is zero.
+    fn choose_variant<'a>(&self, variants: &'a [Variant], variable: &str) -> Option<&'a Variant> {
+        if let Some(current) = self.env.get(variable) {
+            let current = current.to_string();
+            if let Some(v) = variants.iter().find(|v| v.name == current) {
+                return Some(v);
+            }
+        }
+        let total: u64 = variants.iter().map(|v| u64::from(v.weight)).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut pick = self.rng.gen_range(total);
+        variants.iter().find(|v| {
+            let weight = u64::from(v.weight);
+            if pick < weight {
+                true
+            } else {
+                pick -= weight;
+                false
+            }
+        })
+    }
+
+    /// Move past any nodes with nothing to ask, returning the last step taken if any.
+    /// Gives up after visiting every node once, in case they form a loop.
+    fn pass_through_nodes(&mut self) -> Option<Step> {
+        let mut step = None;
+        for _ in 0..self.flow.len() {
+            match self.pass_through() {
+                Some((next, cause, received)) => {
+                    let moved = self.move_to(next, cause, received);
+                    step = Some(moved);
+                    // Stay put rather than calling a webhook or running a program again
+                    if !matches!(moved, Step::Transition(_)) {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+        step
+    }
+
+    /// Submit a line of user input to the current node
+    pub fn answer(&mut self, input: &str) -> Result<Step, InputError> {
+        if self.is_finished() {
+            return Err(InputError::Finished);
+        }
+        self.enter_start();
+        if self.is_finished() {
+            return Err(InputError::Finished);
+        }
+        let node = self.current_node;
+        self.transcript.record(Event::Input {
+            node,
+            raw: input.into(),
+        });
+        let accepted = self.validate(input);
+        self.transcript.record(Event::Validation {
+            node,
+            accepted: accepted.is_ok(),
+            reason: accepted.as_ref().err().map(|e| e.to_string()),
+        });
+        match accepted? {
+            Accepted::Store(value, destination, points) => {
+                let assignments = self.answer_assignments(value, &points);
+                Ok(self.state_transition(destination, Cause::Answer, assignments))
+            }
+            Accepted::Blank => {
+                // Empty input - move on to the next prompt, or give up
+                self.internal_state += 1;
+                self.prompted_at = None;
+                match &self.flow.nodes[node].node_type {
+                    NodeType::Question(_, fail, qs) if self.internal_state >= qs.len() => {
+                        let destination = *fail;
+                        Ok(self.state_transition(destination, Cause::Exhausted, Vec::new()))
+                    }
+                    _ => Ok(Step::Reprompt),
+                }
+            }
+            Accepted::Dismiss => {
+                Ok(self.state_transition(TERMINATING_NODE, Cause::Answer, Vec::new()))
+            }
+        }
+    }
+
+    /// Check input against the current node without changing anything
+    fn validate(&self, input: &str) -> Result<Accepted, InputError> {
+        use NodeType::*;
+        match &self.flow.nodes[self.current_node].node_type {
+            Question(success, _, _) => {
+                if input.is_empty() {
+                    Ok(Accepted::Blank)
+                } else {
+                    // Store anything else
+                    Ok(Accepted::Store(input.into(), *success, Vec::new()))
+                }
+            }
+            Branching(_, options) => {
+                if input.is_empty() {
+                    return Err(InputError::Empty);
+                }
+                // Check if its a valid option, if so, take the nth one shown
+                let n = input
+                    .parse::<usize>()
+                    .map_err(|e| InputError::Unrecognized(e.to_string()))?;
+                let option = n
+                    .checked_sub(1)
+                    .and_then(|i| self.visible_options(options).nth(i))
+                    .ok_or(InputError::OutOfRange(n))?;
+                // Set variable to option text, as shown
+                Ok(Accepted::Store(
+                    self.resolve(&option.text),
+                    option.destination,
+                    option.points.clone(),
+                ))
+            }
+            // Any input dismisses the exit message
+            Terminating(_) => Ok(Accepted::Dismiss),
+            // Never waits for input, but if stuck there's no way on
+            Routing(_) | Split(_) | Webhook(_) | Command(_) => Ok(Accepted::Dismiss),
+        }
+    }
+
+    /// Attach a transcript sink, replacing any previous one
+    pub fn set_transcript(&mut self, sink: Option<Box<dyn TranscriptSink>>) {
+        self.transcript = Recorder(sink);
+    }
+
+    /// Attach hooks to be told what happens and decide on transitions, replacing any previous ones
+    pub fn set_hooks(&mut self, hooks: Option<Box<dyn Hooks>>) {
+        self.hooks = HookSet(hooks);
+    }
+
+    /// Nodes entered so far, in order, including the current one
+    pub fn path(&self) -> &[NodeId] {
+        &self.path
+    }
+
+    /// Capture where this session has got to
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            current_node: self.current_node,
+            internal_state: self.internal_state,
+            variables: self
+                .env
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            path: self.path.clone(),
+            locale: self.locale.clone(),
+        }
+    }
+
+    /// How the session ended, or has got to so far
+    pub fn outcome(&self) -> Outcome {
+        let last = self.path.last().cloned();
+        let terminating_node = last.filter(|n| {
+            self.is_finished() && matches!(self.flow.nodes[*n].node_type, NodeType::Terminating(_))
+        });
+        Outcome {
+            terminating_node,
+            path: self.path.clone(),
+            variables: self.snapshot().variables,
+            scores: self.scores(),
+        }
+    }
+
+    /// Pick up a session from a snapshot taken against the same nodes.
+    /// Any prompt timer starts again when the prompt is next shown.
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.current_node = snapshot.current_node;
+        self.internal_state = snapshot.internal_state;
+        self.env = Env(snapshot.variables.into_iter().collect());
+        self.path = snapshot.path;
+        self.locale = snapshot.locale;
+        self.prompted_at = None;
+    }
+
+    /// Set the timeout used by nodes without their own `@timeout`
+    pub fn set_timeout(&mut self, timeout: Option<Timeout>) {
+        self.timeout = timeout;
+    }
+
+    /// Replace the clock used to measure timeouts
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = SharedClock(clock);
+    }
+
+    /// Replace the randomness split nodes choose variants with, such as a seeded generator
+    pub fn set_rng(&mut self, rng: impl RngCore + Send + 'static) {
+        self.rng = SharedRng::new(rng);
+    }
+
+    /// Choose split variants from a generator seeded with `seed`, so the same seed picks the same variants
+    pub fn set_seed(&mut self, seed: u64) {
+        self.set_rng(StdRng::seed_from_u64(seed));
+    }
+
+    /// Timeout in effect for the current node, if any
+    pub fn current_timeout(&self) -> Option<Timeout> {
+        if self.is_finished() {
+            return None;
+        }
+        self.flow.nodes[self.current_node].timeout.or(self.timeout)
+    }
+
+    /// How long is left before the shown prompt times out.
+    /// `None` if there is no timeout or the prompt hasn't been shown yet.
+    pub fn time_remaining(&self) -> Option<Duration> {
+        let timeout = self.current_timeout()?;
+        let elapsed = self.clock.0.now().duration_since(self.prompted_at?);
+        Some(timeout.after.checked_sub(elapsed).unwrap_or_default())
+    }
+
+    /// Apply the timeout if the shown prompt has waited too long.
+    /// Returns the resulting step, or `None` if there is still time.
+    pub fn check_timeout(&mut self) -> Option<Step> {
+        if self.time_remaining()? > Duration::from_secs(0) {
+            return None;
+        }
+        let timeout = self.current_timeout()?;
+        self.transcript.record(Event::TimedOut {
+            node: self.current_node,
+        });
+        if let NodeType::Terminating(_) = self.flow.nodes[self.current_node].node_type {
+            // Nobody is left to read the exit message
+            return Some(self.state_transition(TERMINATING_NODE, Cause::Timeout, Vec::new()));
+        }
+        match timeout.action {
+            TimeoutAction::Goto(destination) => {
+                Some(self.state_transition(destination, Cause::Timeout, Vec::new()))
+            }
+            TimeoutAction::Reprompt => {
+                self.prompted_at = None;
+                Some(Step::Reprompt)
+            }
+        }
+    }
+
+    /// Variables an accepted answer sets: the current node's variable, if it has one,
+    /// then the scores a chosen option adds its points to
+    fn answer_assignments(&self, value: String, points: &[(String, i64)]) -> Assignments {
+        let mut ret: Assignments = Vec::new();
+        if let Some(variable) = &self.flow.nodes[self.current_node].variable {
+            ret.push((variable.clone(), value.into()));
+        }
+        for (name, add) in points {
+            let current = match ret.iter().rev().find(|(n, _)| n == name) {
+                Some((_, value)) => value.as_int(),
+                None => self.env.get_int(name),
+            };
+            ret.push((name.clone(), (current.unwrap_or_default() + add).into()));
+        }
+        ret
+    }
+
+    /// Set a variable, recording it and telling the hooks
+    fn record_variable(&mut self, name: &str, value: Value) {
+        self.transcript.record(Event::VariableSet {
+            name: name.into(),
+            value: value.to_string(),
+        });
+        self.hooks.variable_set(name, &value);
+        self.env.set_value(name, value);
+    }
+
+    /// Every score in the flow, zero if nothing has added to it yet
+    pub fn scores(&self) -> BTreeMap<String, i64> {
+        self.flow
+            .scores()
+            .into_iter()
+            .map(|name| {
+                let score = self.env.get_int(&name).unwrap_or_default();
+                (name, score)
+            })
+            .collect()
+    }
+
+    /// State transition setting `assignments`, then past any questions to skip
+    fn state_transition(
+        &mut self,
+        new_state: NodeId,
+        cause: Cause,
+        assignments: Assignments,
+    ) -> Step {
+        match self.move_to(new_state, cause, assignments) {
+            step @ Step::Transition(_) => self.pass_through_nodes().unwrap_or(step),
+            step => step,
+        }
+    }
+
+    /// Move to a node setting `assignments` and recording how, unless the hooks say otherwise.
+    /// The hooks are asked with the variables as the move would leave them, and a veto sets nothing.
+    fn move_to(&mut self, new_state: NodeId, cause: Cause, assignments: Assignments) -> Step {
+        let from = self.current_node;
+        let to = Some(new_state).filter(|n| *n != TERMINATING_NODE);
+        let decision = if assignments.is_empty() || !self.hooks.is_set() {
+            self.hooks.transition(from, to, cause, &self.env)
+        } else {
+            let mut pending = self.env.clone();
+            for (name, value) in &assignments {
+                pending.set_value(name, value.clone());
+            }
+            self.hooks.transition(from, to, cause, &pending)
+        };
+        let new_state = match decision {
+            Decision::Redirect(to) if to < self.flow.nodes.len() => to,
+            // A redirect to a node the flow doesn't have goes ahead as planned
+            Decision::Allow | Decision::Redirect(_) => new_state,
+            Decision::Veto => {
+                // Start the node's prompts over, the last may have been used up
+                self.internal_state = 0;
+                self.prompted_at = None;
+                return Step::Reprompt;
+            }
+        };
+        for (name, value) in assignments {
+            self.record_variable(&name, value);
+        }
+        self.hooks.exit(from, &self.env);
         self.current_node = new_state;
         self.internal_state = 0;
+        self.prompted_at = None;
+        if self.is_finished() {
+            self.transcript.record(Event::Transition {
+                from,
+                to: None,
+                cause,
+            });
+            if self.hooks.is_set() {
+                let outcome = self.outcome();
+                self.hooks.terminate(&outcome);
+            }
+            Step::Finished
+        } else {
+            self.transcript.record(Event::Transition {
+                from,
+                to: Some(new_state),
+                cause,
+            });
+            self.enter(new_state);
+            Step::Transition(new_state)
+        }
+    }
+}
+
+impl FromStr for Flow {
+    type Err = pest::error::Error<Rule>;
+
+    /// Parse nodes from the contents of an input file
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ret = Flow::default();
+        let mut parsed = NodesParser::parse(Rule::nodes, s)?;
+        let nodes = parsed.next().unwrap();
+        let headers: Vec<Pair<Rule>> = nodes
+            .clone()
+            .into_inner()
+            .filter(|p| p.as_rule() == Rule::header)
+            .flat_map(|p| p.into_inner())
+            .collect();
+        let allowed: Vec<&str> = headers
+            .iter()
+            .filter(|p| p.as_rule() == Rule::allow)
+            .flat_map(|p| p.clone().into_inner())
+            .filter(|p| p.as_rule() == Rule::program)
+            .map(|p| p.as_str())
+            .collect();
+        let count = nodes
+            .clone()
+            .into_inner()
+            .filter(|p| p.as_rule() == Rule::node)
+            .count();
+        // Catch malformed conditional blocks, programs not allowed and a missing start now rather than when they're reached
+        for pair in nodes.clone().into_inner().flatten() {
+            let checked = match pair.as_rule() {
+                Rule::string => condition::check(pair.as_str()),
+                Rule::condition => pair.as_str().parse::<Condition>().map(|_| ()),
+                Rule::program_line => match pair.as_str().trim_end() {
+                    program if allowed.contains(&program) => Ok(()),
+                    program => Err(format!("{} isn't allowed by an @allow line", program)),
+                },
+                Rule::start | Rule::entry => {
+                    let node = pair.clone().into_inner().find(|p| p.as_rule() == Rule::int);
+                    let node = node.map(|n| n.as_str()).unwrap_or_default();
+                    match node.parse::<usize>() {
+                        Ok(n) if n < count.max(1) => Ok(()),
+                        _ => Err(format!("no node {} to start at", node)),
+                    }
+                }
+                _ => Ok(()),
+            };
+            checked.map_err(|message| {
+                pest::error::Error::new_from_span(
+                    pest::error::ErrorVariant::CustomError { message },
+                    pair.as_span(),
+                )
+            })?;
+        }
+        ret.read_and_register(nodes);
+        Ok(ret)
+    }
+}
+
+impl FromStr for Nodes {
+    type Err = pest::error::Error<Rule>;
+
+    /// Parse nodes from the contents of an input file
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self::from_flow(Arc::new(s.parse()?)))
     }
 }
 
 impl fmt::Display for Nodes {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        use NodeType::*;
-        match &self.nodes[self.current_node].node_type {
-            Branching(question, options) => {
+        write!(f, "{}", self.current_prompt())
+    }
+}
+
+impl fmt::Display for Prompt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Prompt::Branching { text, options } => {
                 let mut option_string = String::new();
                 for (i, option) in options.iter().enumerate() {
-                    option_string.push_str(&format!("{}. {}\n", i + 1, option.0));
+                    option_string.push_str(&format!("{}. {}\n", i + 1, option));
                 }
-                write!(
-                    f,
-                    "{}\n{}\nEnter choice> ",
-                    self.env.resolve_template(question),
-                    option_string
-                )
+                write!(f, "{}\n{}\nEnter choice> ", text, option_string)
             }
-            Question(_, _, qs) => {
-                if self.internal_state > qs.len() {
-                    // this situation should have been caught by the caller
-                    unreachable!()
-                }
-                write!(
-                    f,
-                    "{}\nEnter string> ",
-                    self.env.resolve_template(&qs[self.internal_state])
-                )
+            Prompt::Question { text, .. } => write!(f, "{}\nEnter string> ", text),
+            Prompt::Terminating { text } => {
+                write!(f, "{}\nGoodbye (enter anything to exit)> ", text)
             }
-            Terminating(message) => write!(
-                f,
-                "{}\nGoodbye (enter anything to exit)> ",
-                self.env.resolve_template(message)
-            ),
         }
     }
 }
 
 #[cfg(test)]
 mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// Unguarded branch option
+    fn option(text: &str, destination: NodeId) -> BranchOption {
+        BranchOption {
+            text: text.into(),
+            destination,
+            points: Vec::new(),
+            guard: None,
+        }
+    }
+
+    #[test]
+    fn test_step_through_input() {
+        let mut nodes: Nodes = include_str!("../input.txt").parse().unwrap();
+        assert_eq!(nodes.answer(""), Ok(Step::Reprompt));
+        assert_eq!(
+            nodes.prompt(),
+            Prompt::Question {
+                text: "Please tell me your name".into(),
+                attempt: 1,
+                attempts: 3,
+            }
+        );
+        assert_eq!(nodes.answer("Arthur"), Ok(Step::Transition(1)));
+        assert_eq!(nodes.answer(""), Err(InputError::Empty));
+        assert_eq!(nodes.answer("0"), Err(InputError::OutOfRange(0)));
+        assert_eq!(nodes.answer("3"), Err(InputError::OutOfRange(3)));
+        assert_eq!(nodes.answer("1"), Ok(Step::Transition(2)));
+        assert_eq!(nodes.answer("1"), Ok(Step::Transition(4)));
+        assert_eq!(
+            nodes.prompt(),
+            Prompt::Terminating {
+                text: "You may pass, Arthur who loves Red, on your noble quest for the The Holy Grail."
+                    .into()
+            }
+        );
+        assert_eq!(nodes.answer(""), Ok(Step::Finished));
+        assert!(nodes.is_finished());
+        assert_eq!(nodes.answer("again"), Err(InputError::Finished));
+    }
+
+    #[test]
+    fn test_transcript() {
+        use transcript::MemorySink;
+        let sink = MemorySink::default();
+        let mut nodes: Nodes = include_str!("../input.txt").parse().unwrap();
+        nodes.set_transcript(Some(Box::new(sink.clone())));
+        nodes.prompt();
+        nodes.answer("").unwrap();
+        nodes.prompt();
+        nodes.answer("Arthur").unwrap();
+        nodes.prompt();
+        nodes.answer("x").unwrap_err();
+        let events: Vec<Event> = sink.entries().into_iter().map(|e| e.event).collect();
+        assert_eq!(
+            events,
+            vec![
+                Event::NodeEntered { node: 0 },
+                Event::Prompted {
+                    node: 0,
+                    text: "What is your name?".into(),
+                    options: vec![],
+                },
+                Event::Input {
+                    node: 0,
+                    raw: "".into()
+                },
+                Event::Validation {
+                    node: 0,
+                    accepted: true,
+                    reason: None
+                },
+                Event::Prompted {
+                    node: 0,
+                    text: "Please tell me your name".into(),
+                    options: vec![],
+                },
+                Event::Input {
+                    node: 0,
+                    raw: "Arthur".into()
+                },
+                Event::Validation {
+                    node: 0,
+                    accepted: true,
+                    reason: None
+                },
+                Event::VariableSet {
+                    name: "NAME".into(),
+                    value: "Arthur".into()
+                },
+                Event::Transition {
+                    from: 0,
+                    to: Some(1),
+                    cause: Cause::Answer
+                },
+                Event::NodeEntered { node: 1 },
+                Event::Prompted {
+                    node: 1,
+                    text: "Arthur, what is your quest?".into(),
+                    options: vec!["The Holy Grail".into(), "Run and Hide".into()],
+                },
+                Event::Input {
+                    node: 1,
+                    raw: "x".into()
+                },
+                Event::Validation {
+                    node: 1,
+                    accepted: false,
+                    reason: Some("Unrecognized input: invalid digit found in string".into())
+                },
+            ]
+        );
+        assert_eq!(nodes.path(), &[0, 1]);
+    }
+
+    #[test]
+    fn test_timeouts() {
+        let clock = Arc::new(ManualClock::default());
+        let mut nodes: Nodes = "@timeout 10:reprompt\n1\n1\n2\nNAME\nName?\n@timeout 5:2\n3\nBye $NAME\n3\nTimed out\n"
+            .parse()
+            .unwrap();
+        nodes.set_clock(clock.clone());
+        nodes.set_timeout(Some(Timeout::new(
+            Duration::from_secs(60),
+            TimeoutAction::Goto(2),
+        )));
+        // Timer doesn't start until the prompt is shown
+        clock.advance(Duration::from_secs(100));
+        assert_eq!(nodes.check_timeout(), None);
+        nodes.prompt();
+        clock.advance(Duration::from_secs(9));
+        assert_eq!(nodes.time_remaining(), Some(Duration::from_secs(1)));
+        assert_eq!(nodes.check_timeout(), None);
+        // The node's own timeout wins over the machine-wide one
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(nodes.check_timeout(), Some(Step::Reprompt));
+        assert_eq!(nodes.current_node(), 0);
+        nodes.prompt();
+        assert_eq!(nodes.answer("Robin"), Ok(Step::Transition(1)));
+        nodes.prompt();
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(nodes.check_timeout(), Some(Step::Finished));
+    }
+
+    #[test]
+    fn test_with_env() {
+        let env = Env::from_json(r#"{"NAME": "Alice", "AGE": 42}"#).unwrap();
+        assert_eq!(env.get_variable("AGE"), "42");
+        assert!(Env::from_json("[1, 2]").is_err());
+        // Asked anyway unless skipping
+        let mut nodes = include_str!("../input.txt")
+            .parse::<Nodes>()
+            .unwrap()
+            .with_env(env.clone());
+        assert_eq!(nodes.prompt().text(), "What is your name?");
+        let sink = transcript::MemorySink::default();
+        let mut nodes = include_str!("../input.txt")
+            .parse::<Nodes>()
+            .unwrap()
+            .with_env(env);
+        nodes.set_transcript(Some(Box::new(sink.clone())));
+        nodes.set_skip_answered(true);
+        assert_eq!(nodes.prompt().text(), "Alice, what is your quest?");
+        assert_eq!(nodes.path(), &[0, 1]);
+        assert!(sink.entries().iter().any(|e| e.event
+            == Event::Transition {
+                from: 0,
+                to: Some(1),
+                cause: Cause::Skipped
+            }));
+    }
+
+    #[test]
+    fn test_typed_env() {
+        let env =
+            Env::from_json(r#"{"BUDGET": 25000, "ACTIVE": "yes", "NAME": ["Arthur", "Bedevere"]}"#)
+                .unwrap();
+        assert_eq!(env.get_int("BUDGET"), Some(25000));
+        assert_eq!(env.get_bool("ACTIVE"), Some(true));
+        assert_eq!(env.get_list("NAME").map(|l| l.len()), Some(2));
+        assert_eq!(env.get_int("NAME"), None);
+        assert_eq!(
+            env.resolve_template_in("$NAME: $BUDGET", "es"),
+            "Arthur, Bedevere: 25.000"
+        );
+        let mut nodes = include_str!("../input.txt")
+            .parse::<Nodes>()
+            .unwrap()
+            .with_env(env);
+        nodes.set_skip_answered(true);
+        assert_eq!(
+            nodes.prompt().text(),
+            "Arthur, Bedevere, what is your quest?"
+        );
+        // Answers are always text
+        nodes.answer("1").unwrap();
+        assert_eq!(
+            nodes.env().get("QUEST"),
+            Some(&Value::from("The Holy Grail"))
+        );
+    }
+
+    #[test]
+    fn test_filters() {
+        let mut flow: Flow = "1\n1\n1\nNAME\nWho goes there?\n/* */\n3\nHalt, ${NAME|trim|capitalize|royal} - ${TITLE|default:\"knight\"|upper}, $NAME\n"
+            .parse()
+            .unwrap();
+        flow.filters_mut()
+            .register("royal", |text, _| format!("Sir {}", text));
+        let mut nodes = Nodes::from_flow(Arc::new(flow));
+        nodes.answer(" lancelot").unwrap();
+        assert_eq!(
+            nodes.prompt().text(),
+            "Halt, Sir Lancelot - KNIGHT,  lancelot"
+        );
+        assert!(nodes.unresolved_variables().is_empty());
+        assert_eq!(
+            template_variables("${A|default:x} ${B|upper} $C"),
+            vec!["A", "B", "C"]
+        );
+        assert_eq!(
+            required_variables("${A|default:x} ${B|upper} $C"),
+            vec!["B", "C"]
+        );
+        // Not a variable name, shown as written
+        let env = Env::default();
+        assert_eq!(env.resolve_template("${lower} ${NAME"), "${lower} {NAME");
+        assert_eq!(
+            env.unresolved("{{#if NAME}}$NAME{{else}}$FRIEND{{/if}}"),
+            vec!["FRIEND"]
+        );
+        // Names are the same wherever they appear
+        let mut env = Env::default();
+        env.set_variable("N7", "Done".into());
+        env.set_variable("GOOGLE_DONE", "yes".into());
+        assert_eq!(
+            env.resolve_template("$N7, ${GOOGLE_DONE|upper}{{#if N7}}!{{/if}}"),
+            "Done, YES!"
+        );
+        assert_eq!(
+            template_variables("$N7 ${GOOGLE_DONE} ${7X} $_X"),
+            vec!["N7", "GOOGLE_DONE"]
+        );
+    }
+
+    #[test]
+    fn test_conditions() {
+        let mut nodes = Nodes::from_file("AdReadiness.txt").unwrap();
+        for input in &["1", "Alice", "1", "1", "1", "1"] {
+            nodes.answer(input).unwrap();
+        }
+        assert_eq!(nodes.current_node(), 3);
+        assert_eq!(
+            nodes.prompt().text(),
+            "Alice, we need for you to open access to the rest of these four platforms.  We suggest the FaceBook ID next.  Please select one."
+        );
+        // Once Facebook is done as well the suggestion goes, the rest of the platforms stay
+        for input in &["1", "1", "1", "1"] {
+            nodes.answer(input).unwrap();
+        }
+        assert_eq!(nodes.current_node(), 3);
+        assert_eq!(nodes.env().get_variable("N3"), "FaceBook ID");
+        assert_eq!(
+            nodes.prompt().text(),
+            "Alice, we need for you to open access to the rest of these four platforms.  Please select one."
+        );
+        // Before any is chosen, the other branch
+        let mut env = Env::default();
+        env.set_variable("NAME", "Alice".into());
+        let mut nodes = Nodes::from_flow_at(nodes.flow().clone(), "returning", env).unwrap();
+        assert_eq!(
+            nodes.prompt().text(),
+            "Alice, we need for you to open access to these four platforms, one at a time.  Please select one."
+        );
+        // Malformed blocks fail to load
+        let e = "3\nBye{{#if NAME}}, $NAME\n".parse::<Flow>().unwrap_err();
+        assert!(e.to_string().contains("is never closed"));
+    }
+
+    #[test]
+    fn test_hooks() {
+        use hooks::Decision;
+        use std::sync::Mutex;
+
+        /// Writes down everything it's told, sends anyone running to node 5, and anyone choosing blue nowhere
+        struct Log(Arc<Mutex<Vec<String>>>);
+
+        impl Hooks for Log {
+            fn on_enter(&mut self, node: NodeId, _env: &Env) {
+                self.0.lock().unwrap().push(format!("enter {}", node));
+            }
+            fn on_exit(&mut self, node: NodeId, _env: &Env) {
+                self.0.lock().unwrap().push(format!("exit {}", node));
+            }
+            fn on_variable_set(&mut self, name: &str, value: &Value) {
+                self.0.lock().unwrap().push(format!("{} = {}", name, value));
+            }
+            fn on_transition(
+                &mut self,
+                from: NodeId,
+                to: Option<NodeId>,
+                _cause: Cause,
+                env: &Env,
+            ) -> Decision {
+                match (from, to) {
+                    (0, Some(3)) => Decision::Veto,
+                    (1, Some(3)) => Decision::Redirect(5),
+                    (2, Some(5)) => Decision::Redirect(99),
+                    (4, None) if env.get_variable("COLOR") == "Red" => Decision::Veto,
+                    _ => Decision::Allow,
+                }
+            }
+            fn on_terminate(&mut self, outcome: &Outcome) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("end {:?}", outcome.terminating_node));
+            }
+            fn on_action(&mut self, action: &str, node: NodeId, _env: &Env) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("action {} at {}", action, node));
+            }
+        }
+
+        let flow =
+            include_str!("../input.txt").replace("/* Node 4 */\n", "/* Node 4 */\n@action pass\n");
+        assert_eq!(flow.parse::<Flow>().unwrap().actions(), vec!["pass"]);
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut nodes: Nodes = flow.parse().unwrap();
+        nodes.set_hooks(Some(Box::new(Log(log.clone()))));
+        // Giving up on the name is vetoed, so it starts asking again
+        for _ in 0..3 {
+            nodes.answer("").unwrap();
+        }
+        assert_eq!(nodes.current_node(), 0);
+        assert_eq!(nodes.prompt().text(), "What is your name?");
+        nodes.answer("Arthur").unwrap();
+        nodes.answer("1").unwrap();
+        assert_eq!(nodes.answer("1"), Ok(Step::Transition(4)));
+        // Finishing with COLOR set to Red is vetoed, so the goodbye is shown again
+        assert_eq!(nodes.answer(""), Ok(Step::Reprompt));
+        assert!(!nodes.is_finished());
+        assert_eq!(
+            log.lock().unwrap().clone(),
+            vec![
+                "enter 0",
+                "NAME = Arthur",
+                "exit 0",
+                "enter 1",
+                "QUEST = The Holy Grail",
+                "exit 1",
+                "enter 2",
+                "COLOR = Red",
+                "exit 2",
+                "enter 4",
+                "action pass at 4",
+            ]
+        );
+        // Running away is redirected
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut nodes: Nodes = flow.parse().unwrap();
+        nodes.set_hooks(Some(Box::new(Log(log.clone()))));
+        nodes.answer("Robin").unwrap();
+        assert_eq!(nodes.answer("2"), Ok(Step::Transition(5)));
+        assert_eq!(nodes.answer(""), Ok(Step::Finished));
+        assert_eq!(
+            log.lock().unwrap()[4..].to_vec(),
+            vec![
+                "QUEST = Run and Hide",
+                "exit 1",
+                "enter 5",
+                "exit 5",
+                "end Some(5)"
+            ]
+        );
+        // A redirect to a node that doesn't exist is ignored
+        let mut nodes: Nodes = flow.parse().unwrap();
+        nodes.set_hooks(Some(Box::new(Log(Arc::new(Mutex::new(Vec::new()))))));
+        nodes.answer("Galahad").unwrap();
+        nodes.answer("1").unwrap();
+        assert_eq!(nodes.answer("2"), Ok(Step::Transition(5)));
+    }
+
+    #[test]
+    fn test_vetoed_answer() {
+        use hooks::Decision;
+        use std::sync::Mutex;
+
+        /// Turns down "Yes", keeping a note of the variables set
+        struct NoYes(Arc<Mutex<Vec<String>>>);
+
+        impl Hooks for NoYes {
+            fn on_variable_set(&mut self, name: &str, value: &Value) {
+                self.0.lock().unwrap().push(format!("{} = {}", name, value));
+            }
+            fn on_transition(
+                &mut self,
+                _from: NodeId,
+                _to: Option<NodeId>,
+                _cause: Cause,
+                env: &Env,
+            ) -> Decision {
+                match env.get_variable("CHOICE").as_str() {
+                    "Yes" => Decision::Veto,
+                    _ => Decision::Allow,
+                }
+            }
+        }
+
+        let set = Arc::new(Mutex::new(Vec::new()));
+        let mut nodes: Nodes =
+            "2\nCHOICE\nReady?\nYes:1 +2 READY\nNo:1 +1 READY\n3\nScore $READY\n"
+                .parse()
+                .unwrap();
+        nodes.set_hooks(Some(Box::new(NoYes(set.clone()))));
+        // Vetoed twice, nothing stored or scored
+        for _ in 0..2 {
+            assert_eq!(nodes.answer("1"), Ok(Step::Reprompt));
+            assert_eq!(nodes.scores()["READY"], 0);
+            assert_eq!(nodes.env().get("CHOICE"), None);
+        }
+        assert!(set.lock().unwrap().is_empty());
+        assert_eq!(nodes.answer("2"), Ok(Step::Transition(1)));
+        assert_eq!(nodes.scores()["READY"], 1);
+        assert_eq!(
+            set.lock().unwrap().clone(),
+            vec!["CHOICE = No", "READY = 1"]
+        );
+    }
+
+    #[test]
+    fn test_guards() {
+        let flow = "@exhausted 3\n2\nPICK\nPick one\nRed:1 if !RED\nBlue:2 if BLUE != Okay\n2\nRED\nRed it is\nOkay:0\n2\nBLUE\nBlue it is\nOkay:0\n3\nAll done\n";
+        let mut nodes: Nodes = flow.parse().unwrap();
+        nodes.answer("2").unwrap();
+        nodes.answer("1").unwrap();
+        // Blue is gone, so Red is renumbered
+        assert_eq!(
+            nodes.prompt(),
+            Prompt::Branching {
+                text: "Pick one".into(),
+                options: vec!["Red".into()],
+            }
+        );
+        assert!(nodes.to_string().contains("1. Red") && !nodes.to_string().contains("Blue"));
+        assert_eq!(nodes.answer("2"), Err(InputError::OutOfRange(2)));
+        assert_eq!(nodes.answer("1"), Ok(Step::Transition(1)));
+        // Nothing left to pick
+        assert_eq!(nodes.answer("1"), Ok(Step::Transition(3)));
+        assert_eq!(nodes.prompt().text(), "All done");
+        assert_eq!(nodes.path(), &[0, 2, 0, 1, 0, 3]);
+        let e = "2\nPICK\nPick one\nRed:1 if red\n"
+            .parse::<Flow>()
+            .unwrap_err();
+        assert!(e.to_string().contains("`red` needs a variable name"));
+    }
+
+    #[test]
+    fn test_scoring() {
+        let flow = "2\nSITE\nDo you have a website?\nYes:1 +3 READY\nNo:1\n2\nADS\nDo you run ads?\nYes:2 +4 READY +1 SPEND\nNot yet:2 -1 READY\n4\nREADY\n7+:5\n4-6:4\n*:3\n3\nNot ready ($READY)\n3\nPartly ready ($READY)\n3\nReady ($READY)\n";
+        let mut nodes: Nodes = flow.parse().unwrap();
+        assert_eq!(
+            nodes.flow().variables(),
+            vec!["SITE", "READY", "ADS", "SPEND"]
+        );
+        assert!(validate::validate(&nodes).is_empty());
+        nodes.answer("1").unwrap();
+        assert_eq!(nodes.answer("1"), Ok(Step::Transition(5)));
+        assert_eq!(nodes.prompt().text(), "Ready (7)");
+        assert_eq!(nodes.path(), &[0, 1, 2, 5]);
+        nodes.answer("").unwrap();
+        let outcome = nodes.outcome();
+        assert_eq!(outcome.terminating_node, Some(5));
+        assert_eq!(outcome.scores["READY"], 7);
+        assert_eq!(outcome.scores["SPEND"], 1);
+        // Below every band but the catch-all
+        let mut nodes: Nodes = flow.parse().unwrap();
+        nodes.answer("2").unwrap();
+        nodes.answer("2").unwrap();
+        assert_eq!(nodes.prompt().text(), "Not ready (-1)");
+        assert_eq!(nodes.scores()["SPEND"], 0);
+        let band: Flow = "4\nX\n-5--1:0\n".parse().unwrap();
+        assert_eq!(
+            band.nodes[0].node_type,
+            NodeType::Routing(vec![Band {
+                min: Some(-5),
+                max: Some(-1),
+                destination: 0
+            }])
+        );
+    }
+
+    #[test]
+    fn test_split() {
+        let flow =
+            "5\nGREETING\nFormal:1 weight 3\nCasual:2\nNever:2 weight 0\n3\nGood day\n3\nHey\n";
+        let parsed: Flow = flow.parse().unwrap();
+        assert_eq!(
+            parsed.nodes[0].node_type,
+            NodeType::Split(vec![
+                Variant {
+                    name: "Formal".into(),
+                    weight: 3,
+                    destination: 1
+                },
+                Variant {
+                    name: "Casual".into(),
+                    weight: 1,
+                    destination: 2
+                },
+                Variant {
+                    name: "Never".into(),
+                    weight: 0,
+                    destination: 2
+                },
+            ])
+        );
+        // The same seed draws the same variants
+        let draw = |seed| {
+            let mut nodes: Nodes = flow.parse().unwrap();
+            nodes.set_seed(seed);
+            nodes.prompt();
+            (nodes.current_node(), nodes.env().get_variable("GREETING"))
+        };
+        let draws: Vec<_> = (0..40).map(draw).collect();
+        assert_eq!(draws, (0..40).map(draw).collect::<Vec<_>>());
+        let formal = draws.iter().filter(|(n, _)| *n == 1).count();
+        assert!(formal > 20 && formal < 40, "{} of 40 formal", formal);
+        assert!(draws
+            .iter()
+            .all(|(n, v)| (*n, v.as_str()) == (1, "Formal") || (*n, v.as_str()) == (2, "Casual")));
+        // A variant already given is kept
+        let mut env = Env::default();
+        env.set_variable("GREETING", "Never".into());
+        let mut nodes = Nodes::from_flow(Arc::new(parsed)).with_env(env);
+        assert_eq!(nodes.prompt().text(), "Hey");
+        // Nothing to draw from ends execution
+        let mut nodes: Nodes = "5\nGREETING\nNever:1 weight 0\n3\nHey\n".parse().unwrap();
+        nodes.prompt();
+        assert!(nodes.is_finished());
+    }
+
+    #[test]
+    fn test_locale() {
+        let mut nodes = Nodes::from_file("AdReadiness.txt").unwrap();
+        nodes.set_locale(Some("es".into()));
+        assert_eq!(nodes.locale(), "es");
+        assert_eq!(
+            nodes.prompt(),
+            Prompt::Branching {
+                text: "Bienvenido a Winnow.  Para empezar necesitamos hacerle algunas preguntas y que haga algunas cosas".into(),
+                options: vec!["Empezar".into(), "No estoy seguro".into()],
+            }
+        );
+        nodes.answer("1").unwrap();
+        nodes.answer("Ana").unwrap();
+        assert_eq!(
+            nodes.prompt().text(),
+            "Ana, necesitamos que nos dé acceso a estas cuatro plataformas, una por una.  Por favor, elija una."
+        );
+        assert_eq!(nodes.env().get_variable("N0"), "Empezar");
+        // Unknown locales fall back to the default
+        let mut nodes = Nodes::from_file("AdReadiness.txt").unwrap();
+        nodes.set_locale(Some("fr".into()));
+        assert_eq!(nodes.prompt().text(), "Welcome to Winnow.  In order to get started we need to ask you a few question and have you do a few things");
+    }
+
     #[test]
     fn test_parse_input() {
         use super::Nodes;
         use pretty_assertions::assert_eq;
-        let mut test = Nodes::default();
+        let mut test = Flow::default();
         // Node 0
         test.register_question_node(
             1,
@@ -513,13 +2627,13 @@ mod test {
         test.register_branching_node(
             "QUEST",
             "$NAME, what is your quest?",
-            vec![("The Holy Grail".into(), 2), ("Run and Hide".into(), 3)],
+            vec![option("The Holy Grail", 2), option("Run and Hide", 3)],
         );
         // Node 2
         test.register_branching_node(
             "COLOR",
             "$NAME, who seeks $QUEST, what is your favorite color?",
-            vec![("Red".into(), 4), ("I mean blue".into(), 5)],
+            vec![option("Red", 4), option("I mean blue", 5)],
         );
         // Node 3
         test.register_terminating_node(
@@ -531,6 +2645,6 @@ mod test {
         );
         // Node 5
         test.register_terminating_node("AAAARRRRGGGGGHHHHH");
-        assert_eq!(Nodes::new(None), test);
+        assert_eq!(Nodes::new(None), Nodes::from_flow(Arc::new(test)));
     }
 }
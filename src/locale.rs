@@ -0,0 +1,242 @@
+//! # locale
+//! Any prompt, option or exit message in a flow can be a message key instead of text:
+//! `#` followed by letters, digits, `.`, `_` or `-`, like `#welcome`.
+//! Each locale supplies the text for its keys in a catalogue kept next to the flow,
+//! named `<flow>.<locale>.messages`:
+//! ```txt
+//! /* AdReadiness.es.messages */
+//! welcome = Bienvenido a Winnow.
+//! start = Empezar
+//! ```
+//! A session shows the text from its own locale, falling back to the flow's default locale,
+//! `en` unless set otherwise, and then to the bare key.
+//! Catalogue text can use `$VARIABLES` like any other prompt.
+
+use pest::Parser;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+    str::FromStr,
+};
+
+use crate::condition;
+
+#[derive(Parser)]
+#[grammar = "catalogue.pest"]
+struct CatalogueParser;
+
+/// Locale used when a session doesn't choose one
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// File extension for catalogues
+const EXTENSION: &str = "messages";
+
+/// The key a text refers to, if it is a message key rather than text
+pub fn message_key(text: &str) -> Option<&str> {
+    let key = text.strip_prefix('#')?;
+    if !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-')
+    {
+        Some(key)
+    } else {
+        None
+    }
+}
+
+/// Texts for one locale, by key
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Catalogue(HashMap<String, String>);
+
+impl Catalogue {
+    /// Text for a key
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    /// Set the text for a key
+    pub fn insert(&mut self, key: &str, text: &str) {
+        self.0.insert(key.into(), text.into());
+    }
+}
+
+impl FromStr for Catalogue {
+    type Err = String;
+
+    /// Parse the contents of a catalogue file
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Every line needs an ending, including the last
+        let mut source = s.to_string();
+        if !source.ends_with('\n') {
+            source.push('\n');
+        }
+        let mut parsed =
+            CatalogueParser::parse(Rule::catalogue, &source).map_err(|e| e.to_string())?;
+        let mut ret = Self::default();
+        for message in parsed.next().unwrap().into_inner() {
+            if message.as_rule() == Rule::message {
+                let mut inner = message.into_inner();
+                let key = inner.next().unwrap().as_str();
+                let text = inner.next().unwrap().as_str();
+                condition::check(text).map_err(|e| format!("{}: {}", key, e))?;
+                ret.insert(key, text.trim_end());
+            }
+        }
+        Ok(ret)
+    }
+}
+
+/// Every catalogue for a flow
+#[derive(Clone, Debug, PartialEq)]
+pub struct Catalogues {
+    /// Locale to fall back to
+    default_locale: String,
+    /// Catalogues by locale
+    locales: BTreeMap<String, Catalogue>,
+}
+
+impl Default for Catalogues {
+    fn default() -> Self {
+        Self {
+            default_locale: DEFAULT_LOCALE.into(),
+            locales: BTreeMap::new(),
+        }
+    }
+}
+
+impl Catalogues {
+    /// Find and parse every `<flow>.<locale>.messages` file next to `flow_file`
+    pub fn load(flow_file: &Path) -> Result<Self, String> {
+        let mut ret = Self::default();
+        let stem = match flow_file.file_stem() {
+            Some(stem) => format!("{}.", stem.to_string_lossy()),
+            None => return Ok(ret),
+        };
+        let dir = match flow_file.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let entries = fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(EXTENSION) {
+                continue;
+            }
+            let locale = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.strip_prefix(&stem));
+            if let Some(locale) = locale {
+                let catalogue = fs::read_to_string(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|s| s.parse())
+                    .map_err(|e| format!("{}: {}", path.display(), e))?;
+                ret.add(locale, catalogue);
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Add or replace the catalogue for a locale
+    pub fn add(&mut self, locale: &str, catalogue: Catalogue) {
+        self.locales.insert(locale.into(), catalogue);
+    }
+
+    /// Locale to fall back to
+    pub fn default_locale(&self) -> &str {
+        &self.default_locale
+    }
+
+    /// Change the locale to fall back to
+    pub fn set_default_locale(&mut self, locale: &str) {
+        self.default_locale = locale.into();
+    }
+
+    /// Every locale with a catalogue, plus the default
+    pub fn locales(&self) -> Vec<&str> {
+        let mut ret: Vec<&str> = self.locales.keys().map(String::as_str).collect();
+        if !self.locales.contains_key(&self.default_locale) {
+            ret.push(&self.default_locale);
+            ret.sort_unstable();
+        }
+        ret
+    }
+
+    /// Catalogue for a locale
+    pub fn get(&self, locale: &str) -> Option<&Catalogue> {
+        self.locales.get(locale)
+    }
+
+    /// What to show for `text` in `locale`, or the default locale if `None`.
+    /// Anything that isn't a message key is shown as is.
+    pub fn localise<'a>(&'a self, locale: Option<&str>, text: &'a str) -> &'a str {
+        let key = match message_key(text) {
+            Some(key) => key,
+            None => return text,
+        };
+        locale
+            .and_then(|l| self.locales.get(l))
+            .and_then(|c| c.get(key))
+            .or_else(|| self.locales.get(&self.default_locale)?.get(key))
+            .unwrap_or(text)
+    }
+
+    /// Every way `text` can be shown, across all locales
+    pub fn variants<'a>(&'a self, text: &'a str) -> Vec<&'a str> {
+        if message_key(text).is_none() {
+            return vec![text];
+        }
+        let mut ret: Vec<&str> = self
+            .locales()
+            .into_iter()
+            .map(|l| self.localise(Some(l), text))
+            .collect();
+        ret.dedup();
+        ret
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_localise() {
+        assert_eq!(message_key("#welcome.title"), Some("welcome.title"));
+        assert_eq!(message_key("#1 choice"), None);
+        assert_eq!(message_key("Welcome"), None);
+        let mut catalogues = Catalogues::default();
+        catalogues.add(
+            "en",
+            "/* English */\nhello = Hello, $NAME\nbye = Goodbye\n"
+                .parse()
+                .unwrap(),
+        );
+        catalogues.add("es", "hello = Hola, $NAME".parse().unwrap());
+        assert_eq!(catalogues.locales(), vec!["en", "es"]);
+        assert_eq!(catalogues.localise(Some("es"), "#hello"), "Hola, $NAME");
+        assert_eq!(catalogues.localise(None, "#hello"), "Hello, $NAME");
+        // Missing in Spanish, and missing everywhere
+        assert_eq!(catalogues.localise(Some("es"), "#bye"), "Goodbye");
+        assert_eq!(catalogues.localise(Some("es"), "#gone"), "#gone");
+        assert_eq!(catalogues.localise(Some("fr"), "Plain"), "Plain");
+        assert_eq!(
+            catalogues.variants("#hello"),
+            vec!["Hello, $NAME", "Hola, $NAME"]
+        );
+    }
+
+    #[test]
+    fn test_load() {
+        let catalogues = Catalogues::load(Path::new("AdReadiness.txt")).unwrap();
+        assert_eq!(catalogues.locales(), vec!["en", "es"]);
+        assert!("nonsense".parse::<Catalogue>().is_err());
+        assert_eq!(
+            "done = {{else}}".parse::<Catalogue>(),
+            Err("done: `{{else}}` without an `{{#if}}`".into())
+        );
+    }
+}
@@ -7,7 +7,24 @@
 // Copyright SIMC - All Rights Reserved.
 //
 
-use winnow_sm::Nodes;
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use winnow_sm::{
+    analysis::{cycles, simple_paths, Coverage},
+    replay::replay,
+    scenario::parse_scenarios,
+    server::Server,
+    simulate::Simulation,
+    stats::Stats,
+    transcript::{read_json_lines, JsonLinesSink, MemorySink},
+    validate::validate,
+    Env, Flow, Nodes, Timeout,
+};
 
 // Grab package metadata
 const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -25,25 +42,511 @@ fn format_authors(s: &str) -> String {
     ret
 }
 
-/// Return the first command line argument, if present
-fn get_input_file_arg() -> Option<String> {
-    let args: Vec<String> = std::env::args().collect();
-    match args.len() {
-        0 => unreachable!(), // will always contain name of executable
-        1 => None,
-        _ => Some(args[1].clone()),
+/// Command line options
+#[derive(Debug, Default)]
+struct Options {
+    /// First positional argument, if present
+    input_file: Option<String>,
+    /// `--timeout <seconds>:<node|reprompt>`
+    timeout: Option<Timeout>,
+    /// `--transcript <file>`
+    transcript: Option<String>,
+    /// `--tui`
+    tui: bool,
+    /// `--locale <name>`
+    locale: Option<String>,
+    /// Every `--var NAME=value`, in order
+    vars: Vec<(String, String)>,
+    /// `--vars <file.json>`
+    vars_file: Option<String>,
+    /// `--skip-answered`
+    skip_answered: bool,
+    /// `--output <file.json>`
+    output: Option<String>,
+    /// `--csv <file.csv>`
+    csv: Option<String>,
+    /// `--seed <n>`
+    seed: Option<u64>,
+    /// `--allow-commands`
+    allow_commands: bool,
+    /// `--entry <name>`
+    entry: Option<String>,
+}
+
+/// Read options from the command line, exiting with a message on bad input
+fn get_options(args: Vec<String>) -> Options {
+    let mut ret = Options::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--timeout" => {
+                let value = args.next().unwrap_or_default();
+                match value.parse() {
+                    Ok(t) => ret.timeout = Some(t),
+                    Err(e) => exit_with(&e),
+                }
+            }
+            "--transcript" => match args.next() {
+                Some(file) => ret.transcript = Some(file),
+                None => exit_with("--transcript needs a file name"),
+            },
+            "--tui" => ret.tui = true,
+            "--var" => {
+                let value = args.next().unwrap_or_default();
+                match value.split_once('=') {
+                    Some((name, value)) if !name.is_empty() => {
+                        ret.vars.push((name.into(), value.into()))
+                    }
+                    _ => exit_with("--var needs NAME=value"),
+                }
+            }
+            "--vars" => match args.next() {
+                Some(file) => ret.vars_file = Some(file),
+                None => exit_with("--vars needs a file name"),
+            },
+            "--skip-answered" => ret.skip_answered = true,
+            "--output" => match args.next() {
+                Some(file) => ret.output = Some(file),
+                None => exit_with("--output needs a file name"),
+            },
+            "--csv" => match args.next() {
+                Some(file) => ret.csv = Some(file),
+                None => exit_with("--csv needs a file name"),
+            },
+            "--seed" => ret.seed = Some(parse_flag(&arg, args.next())),
+            "--allow-commands" => ret.allow_commands = true,
+            "--entry" => match args.next() {
+                Some(entry) => ret.entry = Some(entry),
+                None => exit_with("--entry needs an entry point name"),
+            },
+            "--locale" => match args.next() {
+                Some(locale) => ret.locale = Some(locale),
+                None => exit_with("--locale needs a locale name"),
+            },
+            _ => {
+                // Any extra trailing args are ignored
+                if ret.input_file.is_none() {
+                    ret.input_file = Some(arg)
+                }
+            }
+        }
     }
+    ret
 }
 
-fn main() {
-    // Display preamble
+/// Prefix for environment variables that set flow variables
+const ENV_PREFIX: &str = "WINNOW_VAR_";
+
+/// Variables known before the flow starts: `WINNOW_VAR_<NAME>` from the environment,
+/// then the `--vars` file, then each `--var`, later ones winning
+fn initial_env(options: &Options) -> Env {
+    let mut ret: Env = std::env::vars()
+        .filter_map(|(k, v)| Some((k.strip_prefix(ENV_PREFIX)?.to_string(), v)))
+        .collect();
+    if let Some(file) = &options.vars_file {
+        let from_file = fs::read_to_string(file)
+            .map_err(|e| e.to_string())
+            .and_then(|s| Env::from_json(&s))
+            .unwrap_or_else(|e| exit_with(&format!("{}: {}", file, e)));
+        for (name, value) in from_file.iter() {
+            ret.set_value(name, value.clone());
+        }
+    }
+    for (name, value) in &options.vars {
+        ret.set_variable(name, value.clone());
+    }
+    ret
+}
+
+/// Report a usage problem and quit
+fn exit_with(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1)
+}
+
+/// `winnowdemo replay <flow> <transcript>`
+fn replay_command(args: &[String]) {
+    let (flow, transcript) = match args {
+        [flow, transcript, ..] => (flow, transcript),
+        _ => exit_with("Usage: winnowdemo replay <flow> <transcript>"),
+    };
+    let nodes = Nodes::from_file(flow).unwrap_or_else(|e| exit_with(&e));
+    let recording = File::open(transcript)
+        .and_then(|f| read_json_lines(BufReader::new(f)))
+        .unwrap_or_else(|e| exit_with(&format!("{}: {}", transcript, e)));
+    match replay(nodes, &recording) {
+        Ok(()) => println!("{} replays cleanly against {}", transcript, flow),
+        Err(d) => {
+            println!("{} diverges from {}\n{}", transcript, flow, d);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `winnowdemo test <flow> [scenarios]`, scenarios default to `<flow>.test.txt`
+fn test_command(args: &[String]) {
+    let flow = match args.first() {
+        Some(flow) => flow,
+        None => exit_with("Usage: winnowdemo test <flow> [scenarios]"),
+    };
+    let scenarios_file = match args.get(1) {
+        Some(file) => file.clone(),
+        None => Path::new(flow)
+            .with_extension("test.txt")
+            .to_string_lossy()
+            .into_owned(),
+    };
+    let scenarios = fs::read_to_string(&scenarios_file)
+        .map_err(|e| e.to_string())
+        .and_then(|s| parse_scenarios(&s))
+        .unwrap_or_else(|e| exit_with(&format!("{}: {}", scenarios_file, e)));
+    let flow = Arc::new(Flow::from_file(flow).unwrap_or_else(|e| exit_with(&e)));
+    let mut failed = 0;
+    for scenario in &scenarios {
+        let mismatches = scenario.run(Nodes::from_flow(flow.clone()));
+        if mismatches.is_empty() {
+            println!("PASS {}", scenario.name);
+        } else {
+            failed += 1;
+            println!("FAIL {}", scenario.name);
+            for m in mismatches {
+                println!("  {}", m);
+            }
+        }
+    }
+    println!(
+        "\n{} passed, {} failed ({})",
+        scenarios.len() - failed,
+        failed,
+        scenarios_file
+    );
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+/// Render a path as `0 -> 1 -> 3`
+fn format_path(path: &[usize]) -> String {
+    let names: Vec<String> = path.iter().map(|n| n.to_string()).collect();
+    names.join(" -> ")
+}
+
+/// `winnowdemo paths <flow> [recordings...]`, recordings are transcripts (`.jsonl`) or scenario files
+fn paths_command(args: &[String]) {
+    let flow = match args.first() {
+        Some(flow) => flow,
+        None => exit_with("Usage: winnowdemo paths <flow> [recordings...]"),
+    };
+    let nodes = Nodes::from_file(flow).unwrap_or_else(|e| exit_with(&e));
+    for (terminal, paths) in simple_paths(&nodes, nodes.metadata().start) {
+        println!("{} paths to node {}", paths.len(), terminal);
+        for path in paths {
+            println!("  {}", format_path(&path));
+        }
+    }
+    let found = cycles(&nodes);
+    println!("{} cycles", found.len());
+    for cycle in found {
+        println!("  {} -> {}", format_path(&cycle), cycle[0]);
+    }
+    if args.len() < 2 {
+        return;
+    }
+    let mut coverage = Coverage::new(&nodes);
+    for recording in &args[1..] {
+        if recording.ends_with(".jsonl") {
+            let entries = File::open(recording)
+                .and_then(|f| read_json_lines(BufReader::new(f)))
+                .unwrap_or_else(|e| exit_with(&format!("{}: {}", recording, e)));
+            coverage.record(&entries);
+        } else {
+            let scenarios = fs::read_to_string(recording)
+                .map_err(|e| e.to_string())
+                .and_then(|s| parse_scenarios(&s))
+                .unwrap_or_else(|e| exit_with(&format!("{}: {}", recording, e)));
+            for scenario in scenarios {
+                let sink = MemorySink::default();
+                let mut nodes = Nodes::from_flow(nodes.flow().clone());
+                nodes.set_transcript(Some(Box::new(sink.clone())));
+                scenario.run(nodes);
+                coverage.record(&sink.entries());
+            }
+        }
+    }
+    println!("\n{}", coverage);
+    for node in coverage.unvisited_nodes() {
+        println!("Never visited: node {}", node);
+    }
+    for edge in coverage.untaken_edges() {
+        println!("Never taken: {}", edge);
+    }
+}
+
+/// `winnowdemo stats <flow> <transcripts...> [--json]`
+fn stats_command(args: &[String]) {
+    let usage = "Usage: winnowdemo stats <flow> <transcripts...> [--json]";
+    let json = args.iter().any(|a| a == "--json");
+    let mut files = args.iter().filter(|a| *a != "--json");
+    let flow = files.next().unwrap_or_else(|| exit_with(usage));
+    let nodes = Nodes::from_file(flow).unwrap_or_else(|e| exit_with(&e));
+    let mut stats = Stats::new(&nodes);
+    for transcript in files {
+        let entries = File::open(transcript)
+            .and_then(|f| read_json_lines(BufReader::new(f)))
+            .unwrap_or_else(|e| exit_with(&format!("{}: {}", transcript, e)));
+        stats.record(&entries);
+    }
+    if stats.sessions == 0 {
+        exit_with(usage);
+    }
+    if json {
+        println!("{}", stats.to_json());
+    } else {
+        print!("{}", stats);
+    }
+}
+
+/// `winnowdemo check <flow>`
+fn check_command(args: &[String]) {
+    let flow = match args.first() {
+        Some(flow) => flow,
+        None => exit_with("Usage: winnowdemo check <flow>"),
+    };
+    let nodes = Nodes::from_file(flow).unwrap_or_else(|e| exit_with(&e));
+    let problems = validate(&nodes);
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    if problems.is_empty() {
+        println!("{}: no problems found", flow);
+    } else {
+        println!("\n{}: {} problems found", flow, problems.len());
+        std::process::exit(1);
+    }
+}
+
+/// `winnowdemo serve <flow>... [--addr <host:port>] [--expire <secs>] [--state <file>] [--transcripts <dir>]`
+fn serve_command(args: &[String]) {
+    let mut server = Server::default();
+    let mut addr = "127.0.0.1:8080".to_string();
+    let mut state = None;
+    let mut args = args.iter().cloned();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--addr" => {
+                addr = args
+                    .next()
+                    .unwrap_or_else(|| exit_with("--addr needs <host:port>"))
+            }
+            "--state" => {
+                state = Some(
+                    args.next()
+                        .unwrap_or_else(|| exit_with("--state needs a file")),
+                )
+            }
+            "--expire" => server.set_expiry(Duration::from_secs(parse_flag(&arg, args.next()))),
+            "--transcripts" => {
+                let dir = args
+                    .next()
+                    .unwrap_or_else(|| exit_with("--transcripts needs a directory"));
+                if let Err(e) = server.set_transcripts(PathBuf::from(&dir)) {
+                    exit_with(&format!("{}: {}", dir, e));
+                }
+            }
+            _ => {
+                let name = Path::new(&arg)
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| arg.clone());
+                let flow = Flow::from_file(&arg).unwrap_or_else(|e| exit_with(&e));
+                println!(
+                    "Serving {} as {}, locales {}",
+                    arg,
+                    name,
+                    flow.catalogues().locales().join(", ")
+                );
+                server.add_flow(&name, flow);
+            }
+        }
+    }
+    if let Some(file) = state {
+        if let Err(e) = server.set_state_file(PathBuf::from(file)) {
+            exit_with(&e);
+        }
+        println!("Restored {} sessions", server.session_count());
+    }
+    println!("Listening on http://{}", addr);
+    if let Err(e) = server.serve(&addr) {
+        exit_with(&format!("{}: {}", addr, e));
+    }
+}
+
+/// Parse a numeric flag value, exiting with a message on bad input
+fn parse_flag<T: std::str::FromStr>(flag: &str, value: Option<String>) -> T {
+    value
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_else(|| exit_with(&format!("{} needs a number", flag)))
+}
+
+/// `winnowdemo simulate <flow> [--sessions N] [--steps N] [--seed N] [--weights <node>=<w1,w2,...>]`
+fn simulate_command(args: &[String]) {
+    use rand::{rngs::StdRng, SeedableRng};
+    let mut simulation = Simulation::default();
+    let mut flow = None;
+    let mut seed = rand::random::<u64>();
+    let mut args = args.iter().cloned();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--sessions" => simulation.sessions = parse_flag(&arg, args.next()),
+            "--steps" => simulation.max_steps = parse_flag(&arg, args.next()),
+            "--seed" => seed = parse_flag(&arg, args.next()),
+            "--weights" => {
+                let value = args.next().unwrap_or_default();
+                let mut parts = value.splitn(2, '=');
+                let node = parts.next().and_then(|n| n.parse().ok());
+                let weights: Option<Vec<u32>> = parts
+                    .next()
+                    .and_then(|w| w.split(',').map(|x| x.parse().ok()).collect());
+                match (node, weights) {
+                    (Some(node), Some(weights)) => {
+                        simulation.weights.insert(node, weights);
+                    }
+                    _ => exit_with("--weights needs <node>=<w1,w2,...>"),
+                }
+            }
+            _ => flow = Some(arg),
+        }
+    }
+    let flow = flow.unwrap_or_else(|| {
+        exit_with("Usage: winnowdemo simulate <flow> [--sessions N] [--steps N] [--seed N] [--weights <node>=<w1,w2,...>]")
+    });
+    let parsed = Arc::new(Flow::from_file(&flow).unwrap_or_else(|e| exit_with(&e)));
     println!(
-        "Winnow Automation Demonstration {}\n{}\n\n",
-        VERSION,
-        format_authors(AUTHORS)
+        "Simulating {} sessions of {} with seed {}\n",
+        simulation.sessions, flow, seed
     );
+    // Panics are collected in the report, don't print each one as it happens
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+    let report = simulation.run(
+        || Nodes::from_flow(parsed.clone()),
+        &mut StdRng::seed_from_u64(seed),
+    );
+    std::panic::set_hook(hook);
+    print!("{}", report);
+    if !report.is_clean() {
+        std::process::exit(1);
+    }
+}
+
+fn main() {
+    // skip name of executable
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("replay") => return replay_command(&args[1..]),
+        Some("check") => return check_command(&args[1..]),
+        Some("test") => return test_command(&args[1..]),
+        Some("paths") => return paths_command(&args[1..]),
+        Some("stats") => return stats_command(&args[1..]),
+        Some("simulate") => return simulate_command(&args[1..]),
+        Some("serve") => return serve_command(&args[1..]),
+        _ => {}
+    }
+    let options = get_options(args);
     // Init nodes
-    let mut nodes = Nodes::new(get_input_file_arg());
+    let env = initial_env(&options);
+    let loaded = Nodes::new(options.input_file);
+    let metadata = loaded.metadata();
+    // Display preamble, the flow's own when it names itself
+    match metadata.name {
+        Some(_) => println!("{}\n", metadata),
+        None => println!(
+            "Winnow Automation Demonstration {}\n{}\n\n",
+            VERSION,
+            format_authors(AUTHORS)
+        ),
+    }
+    let missing = metadata.missing(&env, options.entry.as_deref());
+    if !missing.is_empty() {
+        exit_with(&format!(
+            "This flow needs {} set first, with --var, --vars or {}<NAME>",
+            missing.join(", "),
+            ENV_PREFIX
+        ));
+    }
+    let mut nodes = match &options.entry {
+        Some(entry) => {
+            Nodes::from_flow_at(loaded.flow().clone(), entry, env).unwrap_or_else(|e| exit_with(&e))
+        }
+        None => Nodes::from_flow_with(loaded.flow().clone(), env).unwrap_or_else(|e| exit_with(&e)),
+    };
+    nodes.set_timeout(options.timeout);
+    nodes.set_skip_answered(options.skip_answered);
+    if let Some(seed) = options.seed {
+        nodes.set_seed(seed);
+    }
+    if options.allow_commands {
+        allow_commands(&mut nodes);
+    }
+    if let Some(locale) = options.locale {
+        let locales = nodes.flow().catalogues().locales();
+        if !locales.contains(&locale.as_str()) {
+            exit_with(&format!(
+                "No {} catalogue, choose from: {}",
+                locale,
+                locales.join(", ")
+            ));
+        }
+        nodes.set_locale(Some(locale));
+    }
+    if let Some(file) = options.transcript {
+        match File::create(&file) {
+            Ok(f) => nodes.set_transcript(Some(Box::new(JsonLinesSink::new(BufWriter::new(f))))),
+            Err(e) => exit_with(&format!("Cannot create transcript {}: {}", file, e)),
+        }
+    }
     // Run machine
-    nodes.run();
+    if options.tui {
+        run_tui(&mut nodes);
+    } else {
+        nodes.run();
+    }
+    // Keep what was collected
+    let outcome = nodes.outcome();
+    if let Some(file) = options.output {
+        if let Err(e) = fs::write(&file, outcome.to_json()) {
+            exit_with(&format!("Cannot write {}: {}", file, e));
+        }
+    }
+    if let Some(file) = options.csv {
+        if let Err(e) = outcome.append_csv(Path::new(&file), &nodes.flow().variables()) {
+            exit_with(&format!("Cannot append to {}: {}", file, e));
+        }
+    }
+}
+
+/// Let command nodes run the programs their flow allows
+#[cfg(feature = "command")]
+fn allow_commands(nodes: &mut Nodes) {
+    nodes.set_commands_enabled(true);
+}
+
+/// Running programs needs the `command` feature
+#[cfg(not(feature = "command"))]
+fn allow_commands(_: &mut Nodes) {
+    exit_with("--allow-commands needs winnowdemo built with `--features command`");
+}
+
+/// Run the machine full screen
+#[cfg(feature = "tui")]
+fn run_tui(nodes: &mut Nodes) {
+    if let Err(e) = winnow_sm::tui::run(nodes) {
+        exit_with(&format!("Terminal error: {}", e));
+    }
+}
+
+/// Full screen needs the `tui` feature
+#[cfg(not(feature = "tui"))]
+fn run_tui(_: &mut Nodes) {
+    exit_with("--tui needs winnowdemo built with `--features tui`");
 }
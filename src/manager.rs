@@ -0,0 +1,208 @@
+//! # manager
+//! A [`Flow`](../struct.Flow.html) is parsed once and shared; each user gets a
+//! [`Session`](../type.Session.html) pointing at it, which is a [`Nodes`](../struct.Nodes.html)
+//! holding that user's own state alongside the shared flow.
+//! [`SessionManager`](struct.SessionManager.html) hands out ids for sessions and can be used from any number of threads.
+//! Each session has its own lock, so a slow answer in one never holds up another.
+//!
+//! ```
+//! # use winnow_sm::{manager::SessionManager, Flow};
+//! # use std::sync::Arc;
+//! let flow: Arc<Flow> = Arc::new(include_str!("../input.txt").parse().unwrap());
+//! let manager = SessionManager::default();
+//! let id = manager.create(&flow);
+//! let session = manager.get(&id).unwrap();
+//! session.lock().unwrap().answer("Arthur").unwrap();
+//! assert_eq!(session.lock().unwrap().env().get_variable("NAME"), "Arthur");
+//! assert_eq!(manager.len(), 1);
+//! ```
+
+use rand::Rng;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::{timeout::SharedClock, Clock, Flow, Session};
+
+/// A session and when it was last looked up
+#[derive(Debug)]
+struct Slot {
+    /// The session itself
+    session: Arc<Mutex<Session>>,
+    /// Last time it was created, inserted or fetched
+    last_used: Instant,
+}
+
+/// Creates, finds and expires sessions by id
+#[derive(Debug, Default)]
+pub struct SessionManager {
+    /// Live sessions by id
+    sessions: Mutex<HashMap<String, Slot>>,
+    /// How long a session may go unused before `expire` drops it, if ever
+    idle: Option<Duration>,
+    /// Time source for expiry
+    clock: SharedClock,
+}
+
+impl SessionManager {
+    /// Construct a manager that expires sessions left idle for `idle`, or never if `None`
+    pub fn new(idle: Option<Duration>) -> Self {
+        Self {
+            idle,
+            ..Default::default()
+        }
+    }
+
+    /// Replace the clock used to measure idle time
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = SharedClock(clock);
+    }
+
+    /// Lock the session table
+    fn table(&self) -> MutexGuard<'_, HashMap<String, Slot>> {
+        self.sessions.lock().expect("Should lock sessions")
+    }
+
+    /// Start a new session of `flow`, returning its id
+    pub fn create(&self, flow: &Arc<Flow>) -> String {
+        let mut table = self.table();
+        let id = loop {
+            let id = format!("{:016x}", rand::thread_rng().gen::<u64>());
+            if !table.contains_key(&id) {
+                break id;
+            }
+        };
+        table.insert(
+            id.clone(),
+            Slot {
+                session: Arc::new(Mutex::new(Session::from_flow(flow.clone()))),
+                last_used: self.clock.0.now(),
+            },
+        );
+        id
+    }
+
+    /// Add a session under a known id, such as one restored from a snapshot, replacing any already there
+    pub fn insert(&self, id: &str, session: Session) {
+        self.table().insert(
+            id.into(),
+            Slot {
+                session: Arc::new(Mutex::new(session)),
+                last_used: self.clock.0.now(),
+            },
+        );
+    }
+
+    /// Look up a session, counting as a use of it
+    pub fn get(&self, id: &str) -> Option<Arc<Mutex<Session>>> {
+        let now = self.clock.0.now();
+        let mut table = self.table();
+        let slot = table.get_mut(id)?;
+        slot.last_used = now;
+        Some(slot.session.clone())
+    }
+
+    /// Stop tracking a session, returning it if it existed
+    pub fn remove(&self, id: &str) -> Option<Arc<Mutex<Session>>> {
+        self.table().remove(id).map(|slot| slot.session)
+    }
+
+    /// Drop every session left idle for too long, returning their ids
+    pub fn expire(&self) -> Vec<String> {
+        let idle = match self.idle {
+            Some(idle) => idle,
+            None => return Vec::new(),
+        };
+        let now = self.clock.0.now();
+        let mut table = self.table();
+        let expired: Vec<String> = table
+            .iter()
+            .filter(|(_, slot)| now.duration_since(slot.last_used) >= idle)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &expired {
+            table.remove(id);
+        }
+        expired
+    }
+
+    /// Ids of every live session, in no particular order
+    pub fn ids(&self) -> Vec<String> {
+        self.table().keys().cloned().collect()
+    }
+
+    /// Number of live sessions
+    pub fn len(&self) -> usize {
+        self.table().len()
+    }
+
+    /// Whether there are no live sessions
+    pub fn is_empty(&self) -> bool {
+        self.table().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ManualClock, Step};
+    use pretty_assertions::assert_eq;
+    use std::thread;
+
+    /// The bundled demo flow
+    fn flow() -> Arc<Flow> {
+        Arc::new(include_str!("../input.txt").parse().unwrap())
+    }
+
+    #[test]
+    fn test_concurrent_sessions() {
+        let flow = flow();
+        let manager = Arc::new(SessionManager::default());
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let flow = flow.clone();
+                let manager = manager.clone();
+                thread::spawn(move || {
+                    let id = manager.create(&flow);
+                    let session = manager.get(&id).unwrap();
+                    let mut session = session.lock().unwrap();
+                    assert_eq!(
+                        session.answer(&format!("User {}", i)),
+                        Ok(Step::Transition(1))
+                    );
+                    assert_eq!(session.answer("2"), Ok(Step::Transition(3)));
+                    id
+                })
+            })
+            .collect();
+        let ids: Vec<String> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(manager.len(), 8);
+        for (i, id) in ids.iter().enumerate() {
+            let session = manager.get(id).unwrap();
+            let session = session.lock().unwrap();
+            assert_eq!(session.env().get_variable("NAME"), format!("User {}", i));
+            assert!(Arc::ptr_eq(session.flow(), &flow));
+        }
+        assert!(manager.remove(&ids[0]).is_some());
+        assert!(manager.get(&ids[0]).is_none());
+        assert_eq!(manager.len(), 7);
+    }
+
+    #[test]
+    fn test_expire() {
+        let flow = flow();
+        let clock = Arc::new(ManualClock::default());
+        let mut manager = SessionManager::new(Some(Duration::from_secs(60)));
+        manager.set_clock(clock.clone());
+        let stale = manager.create(&flow);
+        let fresh = manager.create(&flow);
+        clock.advance(Duration::from_secs(45));
+        manager.get(&fresh);
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(manager.expire(), vec![stale]);
+        assert_eq!(manager.ids(), vec![fresh]);
+        assert!(SessionManager::default().expire().is_empty());
+    }
+}
@@ -0,0 +1,204 @@
+//! # metadata
+//! A flow file can begin with header lines describing it, all optional and in any order:
+//! ```txt
+//! @name AdReadiness // What the flow is called
+//! @version 1.2 // Which version of it this is
+//! @author SIMC // Who wrote it
+//! @start 3 // Node to begin at, 0 if left out
+//! @entry support:8 // Another node to begin at, by name. Any number of these
+//! @entry returning:3 requires NAME // One needing more variables set than the start does
+//! @requires NAME EMAIL // Variables that must be set before it starts
+//! @outputs READY // Variables it promises to have set by any exit message
+//! ```
+//! They come before the first node, and are read with [`Nodes::metadata`](../struct.Nodes.html#method.metadata).
+//! [`Nodes::from_flow_at`](../struct.Nodes.html#method.from_flow_at) starts a session at a named entry point,
+//! so one flow can serve several journeys. It and [`Nodes::from_flow_with`](../struct.Nodes.html#method.from_flow_with)
+//! refuse to start without the required variables, other constructors leave checking them to the caller.
+//! [`validate`](../validate/fn.validate.html) treats required variables as set from the start,
+//! and reports any exit message that can be reached without setting every output, from the start or any entry point.
+
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use crate::{Env, NodeId};
+
+/// What a flow's header declares
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Metadata {
+    /// Flow name
+    pub name: Option<String>,
+    /// Flow version
+    pub version: Option<String>,
+    /// Flow author
+    pub author: Option<String>,
+    /// Node execution begins at
+    pub start: NodeId,
+    /// Other places execution can begin, by name
+    pub entries: BTreeMap<String, EntryPoint>,
+    /// Variables that must be set before starting
+    pub requires: Vec<String>,
+    /// Variables set by the time an exit message is reached
+    pub outputs: Vec<String>,
+}
+
+/// A named place to begin
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct EntryPoint {
+    /// Node to begin at
+    pub node: NodeId,
+    /// Variables that must be set to begin here, as well as the ones the whole flow requires
+    pub requires: Vec<String>,
+}
+
+impl Metadata {
+    /// Every node execution can begin at, the start first
+    pub fn starts(&self) -> Vec<NodeId> {
+        let mut ret = vec![self.start];
+        for entry in self.entries.values() {
+            if !ret.contains(&entry.node) {
+                ret.push(entry.node);
+            }
+        }
+        ret
+    }
+
+    /// Variables certainly set on beginning at `node`: those the flow requires,
+    /// plus any every entry point there requires if it isn't the start
+    pub fn set_at(&self, node: NodeId) -> BTreeSet<String> {
+        let mut ret: BTreeSet<String> = self.requires.iter().cloned().collect();
+        if node != self.start {
+            let mut entries = self.entries.values().filter(|e| e.node == node);
+            if let Some(first) = entries.next() {
+                let mut common: BTreeSet<&String> = first.requires.iter().collect();
+                for entry in entries {
+                    common.retain(|v| entry.requires.contains(v));
+                }
+                ret.extend(common.into_iter().cloned());
+            }
+        }
+        ret
+    }
+
+    /// Required variables `env` doesn't have to begin at `entry`, or the start if `None`, in the order declared
+    pub fn missing(&self, env: &Env, entry: Option<&str>) -> Vec<&str> {
+        let for_entry = entry
+            .and_then(|e| self.entries.get(e))
+            .map(|e| e.requires.as_slice())
+            .unwrap_or_default();
+        self.requires
+            .iter()
+            .chain(for_entry)
+            .filter(|name| env.get(name).is_none())
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+/// Name, version and author, as far as they're given, like `AdReadiness 1.2 by SIMC`
+impl fmt::Display for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let parts = [
+            self.name.as_deref(),
+            self.version.as_deref(),
+            self.author.as_deref().map(|_| "by"),
+            self.author.as_deref(),
+        ];
+        let parts: Vec<&str> = parts.iter().flatten().copied().collect();
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Flow, Nodes};
+    use pretty_assertions::assert_eq;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_metadata() {
+        let nodes: Nodes = "@name Quest\n@version 2.0\n@author Tim\n@start 1\n@requires NAME\n@outputs COLOR QUEST\n3\nUnused\n2\nCOLOR\n$NAME, what is your favorite color?\nBlue:2\n3\nOff you go\n"
+            .parse()
+            .unwrap();
+        let metadata = nodes.metadata();
+        assert_eq!(
+            *metadata,
+            Metadata {
+                name: Some("Quest".into()),
+                version: Some("2.0".into()),
+                author: Some("Tim".into()),
+                start: 1,
+                entries: BTreeMap::new(),
+                requires: vec!["NAME".into()],
+                outputs: vec!["COLOR".into(), "QUEST".into()],
+            }
+        );
+        assert_eq!(metadata.to_string(), "Quest 2.0 by Tim");
+        assert_eq!(nodes.current_node(), 1);
+        assert_eq!(metadata.missing(&Env::default(), None), vec!["NAME"]);
+        let mut env = Env::default();
+        env.set_variable("NAME", "Tim".into());
+        assert_eq!(metadata.missing(&env, None), Vec::<&str>::new());
+        let flow = nodes.flow().clone();
+        assert_eq!(
+            Nodes::from_flow_with(flow.clone(), Env::default()).unwrap_err(),
+            "This flow needs NAME set first"
+        );
+        let started = Nodes::from_flow_with(flow, env).unwrap();
+        assert_eq!(started.env().get_variable("NAME"), "Tim");
+        // No header at all
+        let nodes: Nodes = "3\nBye\n".parse().unwrap();
+        assert_eq!(*nodes.metadata(), Metadata::default());
+        assert_eq!(nodes.metadata().to_string(), "");
+        // Starting past the end
+        assert!("@start 1\n3\nBye\n".parse::<Nodes>().is_err());
+        assert!("@entry back:1\n3\nBye\n".parse::<Nodes>().is_err());
+    }
+
+    #[test]
+    fn test_entries() {
+        let flow: Arc<Flow> = Arc::new(
+            "@entry back:2 requires NAME\n@entry help:1\n@entry again:2 requires NAME TIME\n1\n1\n1\nNAME\nWhat is your name?\n/* */\n3\nCall us\n3\nWelcome back\n"
+                .parse()
+                .unwrap(),
+        );
+        let metadata = flow.metadata();
+        assert_eq!(metadata.starts(), vec![0, 2, 1]);
+        assert_eq!(
+            metadata.set_at(2),
+            vec!["NAME".to_string()].into_iter().collect()
+        );
+        assert_eq!(metadata.set_at(0), BTreeSet::new());
+        assert_eq!(
+            metadata.missing(&Env::default(), Some("again")),
+            vec!["NAME", "TIME"]
+        );
+        assert_eq!(
+            metadata.missing(&Env::default(), Some("help")),
+            Vec::<&str>::new()
+        );
+        let mut nodes = Nodes::from_flow_at(flow.clone(), "help", Env::default()).unwrap();
+        assert_eq!(nodes.current_node(), 1);
+        assert_eq!(nodes.prompt().text(), "Call us");
+        assert_eq!(nodes.path().to_vec(), vec![1]);
+        assert_eq!(Nodes::from_flow(flow.clone()).current_node(), 0);
+        assert_eq!(
+            Nodes::from_flow_at(flow.clone(), "lost", Env::default()).unwrap_err(),
+            "No entry point lost, choose from: again, back, help"
+        );
+        // Required variables have to be given
+        assert_eq!(
+            Nodes::from_flow_at(flow.clone(), "again", Env::default()).unwrap_err(),
+            "Entry point again needs NAME, TIME set first"
+        );
+        let mut env = Env::default();
+        env.set_variable("NAME", "Tim".into());
+        env.set_variable("TIME", "dawn".into());
+        let mut nodes = Nodes::from_flow_at(flow, "again", env).unwrap();
+        assert_eq!(nodes.prompt().text(), "Welcome back");
+        assert_eq!(nodes.env().get_variable("NAME"), "Tim");
+    }
+}
@@ -0,0 +1,337 @@
+//! # replay
+//! Feed the answers and timeouts from a recorded [transcript](../transcript/index.html) back through
+//! a flow, to check an edited input file still behaves the way it did when the session was recorded.
+//!
+//! The nodes visited, the prompts shown and the final variables are compared, and the first difference is reported.
+//! Split nodes are given the variants the recording was given rather than drawing new ones,
+//! and webhook nodes post nothing, succeeding or failing as they did when recorded.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    sync::Arc,
+};
+
+use crate::{
+    transcript::{Entry, Event, MemorySink},
+    webhook::StubTransport,
+    ManualClock, NodeId, NodeType, Nodes,
+};
+
+/// Something the user saw, in the order they saw it
+#[derive(Clone, Debug, PartialEq)]
+enum Observed {
+    /// Arrived at a node
+    Entered(NodeId),
+    /// Prompt text and options shown at a node
+    Prompted(NodeId, String, Vec<String>),
+}
+
+/// The first point where a replay stopped matching its recording
+#[derive(Clone, Debug, PartialEq)]
+pub enum Divergence {
+    /// A different node was entered. `None` means no node was entered at this point
+    Path {
+        step: usize,
+        expected: Option<NodeId>,
+        actual: Option<NodeId>,
+    },
+    /// The same node showed a different prompt
+    Prompt {
+        node: NodeId,
+        expected: String,
+        actual: String,
+    },
+    /// A recorded timeout can't happen, the node has no timeout
+    Timeout { node: NodeId },
+    /// A final variable differs. `None` means unset
+    Variable {
+        name: String,
+        expected: Option<String>,
+        actual: Option<String>,
+    },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        /// Render an optional value
+        fn or_none<T: fmt::Display>(v: &Option<T>) -> String {
+            match v {
+                Some(v) => v.to_string(),
+                None => "(nothing)".into(),
+            }
+        }
+        match self {
+            Divergence::Path {
+                step,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Step {}: expected node {}, got node {}",
+                step,
+                or_none(expected),
+                or_none(actual)
+            ),
+            Divergence::Prompt {
+                node,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Node {}: expected prompt\n  {}\ngot\n  {}",
+                node, expected, actual
+            ),
+            Divergence::Timeout { node } => {
+                write!(f, "Node {}: recorded a timeout but has none", node)
+            }
+            Divergence::Variable {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Variable {}: expected {}, got {}",
+                name,
+                or_none(expected),
+                or_none(actual)
+            ),
+        }
+    }
+}
+
+/// Pull out what the user saw, in order
+fn observed(entries: &[Entry]) -> Vec<Observed> {
+    entries
+        .iter()
+        .filter_map(|e| match &e.event {
+            Event::NodeEntered { node } => Some(Observed::Entered(*node)),
+            Event::Prompted {
+                node,
+                text,
+                options,
+            } => Some(Observed::Prompted(*node, text.clone(), options.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Fold every variable assignment into the final values
+fn final_variables(entries: &[Entry]) -> BTreeMap<String, String> {
+    let mut ret = BTreeMap::new();
+    for e in entries {
+        if let Event::VariableSet { name, value } = &e.event {
+            ret.insert(name.clone(), value.clone());
+        }
+    }
+    ret
+}
+
+/// Render a prompt with its options for a report
+fn describe(text: &str, options: &[String]) -> String {
+    if options.is_empty() {
+        text.into()
+    } else {
+        format!("{} [{}]", text, options.join(" | "))
+    }
+}
+
+/// Compare what two transcripts showed the user, returning the first difference
+fn compare(expected: &[Observed], actual: &[Observed]) -> Option<Divergence> {
+    let mut step = 0;
+    for i in 0..expected.len().max(actual.len()) {
+        let e = expected.get(i);
+        let a = actual.get(i);
+        match (e, a) {
+            (Some(Observed::Entered(x)), Some(Observed::Entered(y))) if x == y => step += 1,
+            (Some(Observed::Prompted(n, x, xo)), Some(Observed::Prompted(m, y, yo))) if n == m => {
+                if x != y || xo != yo {
+                    return Some(Divergence::Prompt {
+                        node: *n,
+                        expected: describe(x, xo),
+                        actual: describe(y, yo),
+                    });
+                }
+            }
+            _ => {
+                /// Node entered at this point, if that's what happened
+                fn entered(o: Option<&Observed>) -> Option<NodeId> {
+                    match o {
+                        Some(Observed::Entered(n)) => Some(*n),
+                        _ => None,
+                    }
+                }
+                return Some(Divergence::Path {
+                    step,
+                    expected: entered(e),
+                    actual: entered(a),
+                });
+            }
+        }
+    }
+    None
+}
+
+/// Re-run a recorded session through `nodes`, which should be freshly constructed.
+/// Returns the first divergence from the recording, if any.
+pub fn replay(mut nodes: Nodes, recording: &[Entry]) -> Result<(), Divergence> {
+    let sink = MemorySink::default();
+    let clock = Arc::new(ManualClock::default());
+    nodes.set_transcript(Some(Box::new(sink.clone())));
+    nodes.set_clock(clock.clone());
+    nodes.set_webhook_transport(Arc::new(StubTransport::from_recording(recording)));
+    // A split keeps the variant already in its variable, so start with the ones recorded
+    let mut env = nodes.env().clone();
+    for node in &nodes.flow.nodes {
+        if let (NodeType::Split(_), Some(variable)) = (&node.node_type, &node.variable) {
+            let recorded = recording.iter().find_map(|e| match &e.event {
+                Event::VariableSet { name, value } if name == variable => Some(value),
+                _ => None,
+            });
+            if let (Some(value), None) = (recorded, env.get(variable)) {
+                env.set_variable(variable, value.clone());
+            }
+        }
+    }
+    let mut nodes = nodes.with_env(env);
+    for (i, entry) in recording.iter().enumerate() {
+        if nodes.is_finished() {
+            break;
+        }
+        match &entry.event {
+            Event::Prompted { .. } => {
+                nodes.prompt();
+            }
+            Event::Input { raw, .. } => {
+                // Rejected input is part of the recording too
+                let _ = nodes.answer(raw);
+            }
+            Event::TimedOut { .. } => match nodes.current_timeout() {
+                Some(timeout) => {
+                    nodes.prompt();
+                    clock.advance(timeout.after);
+                    nodes.check_timeout();
+                }
+                None => {
+                    // Report any earlier difference first
+                    let so_far = observed(&recording[..i]);
+                    if let Some(d) = compare(&so_far, &observed(&sink.entries())) {
+                        return Err(d);
+                    }
+                    return Err(Divergence::Timeout {
+                        node: nodes.current_node(),
+                    });
+                }
+            },
+            _ => {}
+        }
+    }
+    let replayed = sink.entries();
+    if let Some(d) = compare(&observed(recording), &observed(&replayed)) {
+        return Err(d);
+    }
+    // Same path and prompts, check what was collected along the way
+    let expected = final_variables(recording);
+    let actual: BTreeMap<String, String> = nodes
+        .env()
+        .iter()
+        .map(|(k, v)| (k.clone(), v.to_string()))
+        .collect();
+    let names: BTreeSet<&String> = expected.keys().chain(actual.keys()).collect();
+    for name in names {
+        if expected.get(name) != actual.get(name) {
+            return Err(Divergence::Variable {
+                name: name.clone(),
+                expected: expected.get(name).cloned(),
+                actual: actual.get(name).cloned(),
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// Record a session answering `inputs` against `flow`
+    fn record(flow: &str, inputs: &[&str]) -> Vec<Entry> {
+        let sink = MemorySink::default();
+        let mut nodes: Nodes = flow.parse().unwrap();
+        nodes.set_transcript(Some(Box::new(sink.clone())));
+        for input in inputs {
+            nodes.prompt();
+            let _ = nodes.answer(input);
+        }
+        sink.entries()
+    }
+
+    #[test]
+    fn test_replay_unchanged() {
+        let flow = include_str!("../input.txt");
+        let recording = record(flow, &["", "Arthur", "1", "2", ""]);
+        assert_eq!(replay(flow.parse().unwrap(), &recording), Ok(()));
+    }
+
+    #[test]
+    fn test_replay_timeout() {
+        let flow = include_str!("../input.txt").replace("*/\n1\n", "*/\n@timeout 30:3\n1\n");
+        let sink = MemorySink::default();
+        let clock = Arc::new(ManualClock::default());
+        let mut nodes: Nodes = flow.parse().unwrap();
+        nodes.set_transcript(Some(Box::new(sink.clone())));
+        nodes.set_clock(clock.clone());
+        nodes.prompt();
+        clock.advance(std::time::Duration::from_secs(30));
+        nodes.check_timeout();
+        let recording = sink.entries();
+        assert_eq!(replay(flow.parse().unwrap(), &recording), Ok(()));
+        assert_eq!(
+            replay(include_str!("../input.txt").parse().unwrap(), &recording),
+            Err(Divergence::Timeout { node: 0 })
+        );
+    }
+
+    #[test]
+    fn test_replay_split() {
+        let flow = "5\nGREETING\nFormal:1\nCasual:2\n3\nGood day\n3\nHey\n";
+        for seed in 0..4 {
+            let sink = MemorySink::default();
+            let mut nodes: Nodes = flow.parse().unwrap();
+            nodes.set_transcript(Some(Box::new(sink.clone())));
+            nodes.set_seed(seed);
+            nodes.prompt();
+            nodes.answer("").unwrap();
+            assert_eq!(replay(flow.parse().unwrap(), &sink.entries()), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_replay_divergence() {
+        let flow = include_str!("../input.txt");
+        let recording = record(flow, &["Arthur", "1", "2", ""]);
+        // Reword a prompt
+        let reworded = flow.replace("what is your quest?", "what do you seek?");
+        assert_eq!(
+            replay(reworded.parse().unwrap(), &recording),
+            Err(Divergence::Prompt {
+                node: 1,
+                expected: "Arthur, what is your quest? [The Holy Grail | Run and Hide]".into(),
+                actual: "Arthur, what do you seek? [The Holy Grail | Run and Hide]".into(),
+            })
+        );
+        // Swap where the colors lead
+        let rerouted = flow
+            .replace("Red:4", "Red:5")
+            .replace("I mean blue:5", "I mean blue:4");
+        assert_eq!(
+            replay(rerouted.parse().unwrap(), &recording),
+            Err(Divergence::Path {
+                step: 3,
+                expected: Some(5),
+                actual: Some(4),
+            })
+        );
+    }
+}
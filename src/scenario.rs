@@ -0,0 +1,314 @@
+//! # scenario
+//! A scenario file lives next to its flow, so `AdReadiness.txt` is tested by `AdReadiness.test.txt`.
+//! Each scenario lists the answers a user gives and what should come of them.
+//! Note - `//` comments here are for demonstration only, use `/* */` in the file.
+//! ```txt
+//! /* Comments work the same as in the flow file */
+//! scenario: Arthur seeks the grail  // Starts a scenario, with a name for the report
+//! prompt: What is your name?        // The prompt that should be showing right now
+//! > Arthur                          // An answer, a lone > is a blank answer
+//! > 1
+//! > 1
+//! >                                 // Dismisses the exit message
+//! ends: 4                           // Terminating node execution should finish at
+//! NAME = Arthur                     // Value a variable should hold at the end
+//! QUEST = The Holy Grail
+//! ```
+//! Every line is optional except the `scenario:` line. Answers a node rejects fail the scenario,
+//! and so does an `ends:` line if the answers run out before execution finishes at an exit message.
+
+use pest::{iterators::Pair, Parser};
+use std::{fmt, sync::Arc};
+
+use crate::{analysis::is_terminating, webhook::StubTransport, NodeId, Nodes};
+
+#[derive(Parser)]
+#[grammar = "scenarios.pest"]
+struct ScenariosParser;
+
+/// One line of a scenario, in order
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScenarioStep {
+    /// Answer the current prompt
+    Input(String),
+    /// The current prompt should read this
+    Prompt(String),
+}
+
+/// A named sequence of answers with expected results
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Scenario {
+    /// Shown in the report
+    pub name: String,
+    /// Answers and prompt checks
+    pub steps: Vec<ScenarioStep>,
+    /// Terminating node execution should finish at
+    pub ends: Option<NodeId>,
+    /// Variable values expected at the end
+    pub variables: Vec<(String, String)>,
+}
+
+/// A way a scenario didn't go as expected
+#[derive(Clone, Debug, PartialEq)]
+pub enum Mismatch {
+    /// The prompt showing differed. `None` if execution had already finished
+    Prompt {
+        expected: String,
+        actual: Option<String>,
+    },
+    /// The node didn't accept an answer
+    Rejected {
+        node: NodeId,
+        input: String,
+        reason: String,
+    },
+    /// Execution ended up at a different node
+    Ends { expected: NodeId, actual: NodeId },
+    /// Execution hadn't finished, still waiting for an answer at this node
+    Unfinished { node: NodeId },
+    /// Execution finished at a node with no exit message, like a routing node whose bands didn't match
+    NoExitMessage { node: NodeId },
+    /// A variable held something else, `None` if unset
+    Variable {
+        name: String,
+        expected: String,
+        actual: Option<String>,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mismatch::Prompt { expected, actual } => write!(
+                f,
+                "prompt:\n    - {}\n    + {}",
+                expected,
+                actual.as_deref().unwrap_or("(execution finished)")
+            ),
+            Mismatch::Rejected {
+                node,
+                input,
+                reason,
+            } => write!(f, "node {} rejected \"{}\": {}", node, input, reason),
+            Mismatch::Ends { expected, actual } => {
+                write!(f, "ends:\n    - {}\n    + {}", expected, actual)
+            }
+            Mismatch::Unfinished { node } => {
+                write!(f, "ends: execution hadn't finished, still at node {}", node)
+            }
+            Mismatch::NoExitMessage { node } => write!(
+                f,
+                "ends: execution finished at node {}, which has no exit message",
+                node
+            ),
+            Mismatch::Variable {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{}:\n    - {}\n    + {}",
+                name,
+                expected,
+                actual.as_deref().unwrap_or("(unset)")
+            ),
+        }
+    }
+}
+
+impl Scenario {
+    /// Run against freshly constructed nodes, returning everything that didn't match.
+    /// An empty list means the scenario passed. Webhook nodes post nothing and always succeed.
+    pub fn run(&self, mut nodes: Nodes) -> Vec<Mismatch> {
+        nodes.set_webhook_transport(Arc::new(StubTransport::default()));
+        let mut ret = Vec::new();
+        for step in &self.steps {
+            match step {
+                ScenarioStep::Prompt(expected) => {
+                    let actual = if nodes.is_finished() {
+                        None
+                    } else {
+                        Some(nodes.prompt().text().to_string())
+                    };
+                    if actual.as_ref() != Some(expected) {
+                        ret.push(Mismatch::Prompt {
+                            expected: expected.clone(),
+                            actual,
+                        });
+                    }
+                }
+                ScenarioStep::Input(input) => {
+                    let node = nodes.current_node();
+                    if !nodes.is_finished() {
+                        nodes.prompt();
+                    }
+                    if let Err(e) = nodes.answer(input) {
+                        ret.push(Mismatch::Rejected {
+                            node,
+                            input: input.clone(),
+                            reason: e.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+        if let Some(expected) = self.ends {
+            // Once finished, the last node shown is where it ended
+            let actual = if nodes.is_finished() {
+                *nodes.path().last().unwrap_or(&0)
+            } else {
+                nodes.current_node()
+            };
+            if !nodes.is_finished() {
+                ret.push(Mismatch::Unfinished { node: actual });
+            } else if !is_terminating(&nodes, actual) {
+                ret.push(Mismatch::NoExitMessage { node: actual });
+            }
+            if actual != expected {
+                ret.push(Mismatch::Ends { expected, actual });
+            }
+        }
+        for (name, expected) in &self.variables {
+            let actual = nodes.env().get(name).map(|v| v.to_string());
+            if actual.as_ref() != Some(expected) {
+                ret.push(Mismatch::Variable {
+                    name: name.clone(),
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+        }
+        ret
+    }
+}
+
+/// helper function to parse a scenario rule
+fn parse_scenario(parsed: Pair<Rule>) -> Scenario {
+    let mut ret = Scenario::default();
+    for line in parsed.into_inner() {
+        let rule = line.as_rule();
+        let mut inner = line.into_inner();
+        let first = inner.next().map(|p| p.as_str().to_string());
+        match rule {
+            Rule::title => ret.name = first.unwrap_or_default().trim_end().into(),
+            Rule::input => ret.steps.push(ScenarioStep::Input(
+                first.unwrap_or_default().trim_end().into(),
+            )),
+            Rule::prompt => ret.steps.push(ScenarioStep::Prompt(
+                first.unwrap_or_default().trim_end().into(),
+            )),
+            Rule::ends => ret.ends = first.and_then(|n| n.parse().ok()),
+            Rule::variable => {
+                let value = inner.next().unwrap().as_str().trim_end().to_string();
+                ret.variables.push((first.unwrap(), value));
+            }
+            _ => unreachable!(),
+        }
+    }
+    ret
+}
+
+/// Parse the contents of a scenario file
+pub fn parse_scenarios(s: &str) -> Result<Vec<Scenario>, String> {
+    // Every line needs an ending, including the last
+    let mut source = s.to_string();
+    if !source.ends_with('\n') {
+        source.push('\n');
+    }
+    let mut parsed = ScenariosParser::parse(Rule::scenarios, &source).map_err(|e| e.to_string())?;
+    Ok(parsed
+        .next()
+        .unwrap()
+        .into_inner()
+        .filter(|p| p.as_rule() == Rule::scenario)
+        .map(parse_scenario)
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_scenarios() {
+        let parsed = parse_scenarios(
+            "/* Both kinds */\nscenario: Robin\nprompt: What is your name?\n>\n> Robin\n\n> 2\nends: 3\nNAME = Robin\n",
+        )
+        .unwrap();
+        assert_eq!(
+            parsed,
+            vec![Scenario {
+                name: "Robin".into(),
+                steps: vec![
+                    ScenarioStep::Prompt("What is your name?".into()),
+                    ScenarioStep::Input("".into()),
+                    ScenarioStep::Input("Robin".into()),
+                    ScenarioStep::Input("2".into()),
+                ],
+                ends: Some(3),
+                variables: vec![("NAME".into(), "Robin".into())],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_bundled_scenarios() {
+        for (flow, scenarios) in &[
+            ("input.txt", include_str!("../input.test.txt")),
+            ("AdReadiness.txt", include_str!("../AdReadiness.test.txt")),
+        ] {
+            for scenario in parse_scenarios(scenarios).unwrap() {
+                assert_eq!(
+                    scenario.run(Nodes::from_file(flow).unwrap()),
+                    vec![],
+                    "{}",
+                    scenario.name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_failing_scenario() {
+        let scenario = &parse_scenarios(
+            "scenario: Lancelot\n> Lancelot\n> x\n> 1\nends: 5\nQUEST = Grail\nCOLOR = Blue\n",
+        )
+        .unwrap()[0];
+        assert_eq!(
+            scenario.run(include_str!("../input.txt").parse().unwrap()),
+            vec![
+                Mismatch::Rejected {
+                    node: 1,
+                    input: "x".into(),
+                    reason: "Unrecognized input: invalid digit found in string".into(),
+                },
+                Mismatch::Unfinished { node: 2 },
+                Mismatch::Ends {
+                    expected: 5,
+                    actual: 2,
+                },
+                Mismatch::Variable {
+                    name: "QUEST".into(),
+                    expected: "Grail".into(),
+                    actual: Some("The Holy Grail".into()),
+                },
+                Mismatch::Variable {
+                    name: "COLOR".into(),
+                    expected: "Blue".into(),
+                    actual: None,
+                },
+            ]
+        );
+        // Finishing where no exit message is shown
+        let scenario = &parse_scenarios("scenario: Lost\n> Tim\nends: 1\n").unwrap()[0];
+        let routing: Nodes =
+            "1\n1\n1\nNAME\nWhat is your name?\n/* */\n4\nSCORE\n5+:2\n3\nUnreachable\n"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            scenario.run(routing),
+            vec![Mismatch::NoExitMessage { node: 1 }]
+        );
+    }
+}
@@ -0,0 +1,587 @@
+//! # server
+//! Runs flows for web and other remote front ends. Each flow is named after its file, so
+//! `AdReadiness.txt` is served as `AdReadiness`. Every request and response body is JSON.
+//!
+//! | Request | Does |
+//! |---|---|
+//! | `GET /flows` | List the flows being served |
+//! | `POST /sessions` `{"flow": "AdReadiness", "locale": "es"}` | Start a session, responds `201` with the session. `locale` is optional |
+//! | `GET /sessions/<id>` | Current prompt: text, options, attempt count |
+//! | `POST /sessions/<id>/answer` `{"input": "1"}` | Answer the current prompt, responds `422` if rejected |
+//! | `GET /sessions/<id>/variables` | Variables collected and the path taken |
+//! | `DELETE /sessions/<id>` | Forget a session |
+//!
+//! ```txt
+//! $ curl -s -X POST localhost:8080/sessions -d '{"flow": "input"}'
+//! {"finished":false,"flow":"input","id":"5f0c6a1e9b3d2c47","node":0,"prompt":{"kind":"question","text":"What is your name?","attempt":0,"attempts":3}}
+//! $ curl -s -X POST localhost:8080/sessions/5f0c6a1e9b3d2c47/answer -d '{"input": "Arthur"}'
+//! ```
+//!
+//! Each flow is parsed once and shared by all of its sessions.
+//! Each request is handled on a thread of its own, so one session waiting on a webhook doesn't hold up the rest.
+//! Sessions live in memory, and can be dropped after sitting idle for a while.
+//! Given a state file, they are saved after every change and loaded again on start.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, OpenOptions},
+    io::{self, BufWriter},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::{manager::SessionManager, transcript::JsonLinesSink, Flow, Session, Snapshot};
+
+/// Saved form of a session
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct SavedSession {
+    /// Name of the flow
+    flow: String,
+    /// Where the session had got to
+    snapshot: Snapshot,
+}
+
+/// Status code and JSON body
+#[derive(Debug, PartialEq)]
+pub struct Response {
+    /// HTTP status code
+    pub status: u16,
+    /// Response body
+    pub body: Value,
+}
+
+impl Response {
+    /// Construct a fresh response
+    fn new(status: u16, body: Value) -> Self {
+        Self { status, body }
+    }
+
+    /// Error response with a message
+    fn error(status: u16, message: &str) -> Self {
+        Self::new(status, json!({ "error": message }))
+    }
+}
+
+/// Request body for `POST /sessions`
+#[derive(Deserialize)]
+struct CreateRequest {
+    flow: String,
+    #[serde(default)]
+    locale: Option<String>,
+}
+
+/// Request body for `POST /sessions/<id>/answer`
+#[derive(Deserialize)]
+struct AnswerRequest {
+    input: String,
+}
+
+/// Serves any number of flows to any number of sessions
+#[derive(Debug, Default)]
+pub struct Server {
+    /// Parsed flows by name
+    flows: BTreeMap<String, Arc<Flow>>,
+    /// Running sessions
+    sessions: SessionManager,
+    /// Where to save sessions, if anywhere
+    state_file: Option<PathBuf>,
+    /// Directory for per-session transcripts, if any
+    transcripts: Option<PathBuf>,
+    /// What was last saved of each session, kept for any busy at the next save.
+    /// Held while saving, so requests handled at once don't write over each other
+    saved: Mutex<HashMap<String, SavedSession>>,
+}
+
+impl Server {
+    /// Serve a flow under `name`
+    pub fn add_flow(&mut self, name: &str, flow: Flow) {
+        self.flows.insert(name.into(), Arc::new(flow));
+    }
+
+    /// Forget sessions nobody has touched for `idle`, checked on every request.
+    /// Call before loading a state file.
+    pub fn set_expiry(&mut self, idle: Duration) {
+        self.sessions = SessionManager::new(Some(idle));
+    }
+
+    /// Record each session's transcript to `<dir>/<id>.jsonl`
+    pub fn set_transcripts(&mut self, dir: PathBuf) -> io::Result<()> {
+        fs::create_dir_all(&dir)?;
+        self.transcripts = Some(dir);
+        Ok(())
+    }
+
+    /// Save sessions to `file` after every change, loading any already saved there.
+    /// Sessions for flows no longer served are dropped.
+    pub fn set_state_file(&mut self, file: PathBuf) -> Result<(), String> {
+        if file.exists() {
+            let saved: HashMap<String, SavedSession> = fs::read_to_string(&file)
+                .map_err(|e| e.to_string())
+                .and_then(|s| serde_json::from_str(&s).map_err(|e| e.to_string()))
+                .map_err(|e| format!("{}: {}", file.display(), e))?;
+            for (id, saved) in saved {
+                match self.flows.get(&saved.flow) {
+                    Some(flow) => {
+                        let mut session = Session::from_flow(flow.clone());
+                        session.restore(saved.snapshot);
+                        self.record_transcript(&id, &mut session);
+                        self.sessions.insert(&id, session);
+                    }
+                    None => eprintln!("Dropping session {}: no flow {}", id, saved.flow),
+                }
+            }
+        }
+        self.state_file = Some(file);
+        Ok(())
+    }
+
+    /// Number of sessions in memory
+    pub fn session_count(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Record a session's transcript, if asked to
+    fn record_transcript(&self, id: &str, session: &mut Session) {
+        if let Some(dir) = &self.transcripts {
+            let file = dir.join(format!("{}.jsonl", id));
+            match OpenOptions::new().create(true).append(true).open(&file) {
+                Ok(f) => {
+                    session.set_transcript(Some(Box::new(JsonLinesSink::new(BufWriter::new(f)))))
+                }
+                Err(e) => eprintln!("Cannot record transcript {}: {}", file.display(), e),
+            }
+        }
+    }
+
+    /// Name a session's flow is served under
+    fn flow_name(&self, session: &Session) -> &str {
+        self.flows
+            .iter()
+            .find(|(_, flow)| Arc::ptr_eq(flow, session.flow()))
+            .map(|(name, _)| name.as_str())
+            .unwrap_or_default()
+    }
+
+    /// Write every session to the state file, if there is one
+    fn save(&self) {
+        if let Some(file) = &self.state_file {
+            let mut saved = self.saved.lock().expect("Should lock saved sessions");
+            let ids = self.sessions.ids();
+            saved.retain(|id, _| ids.contains(id));
+            for id in ids {
+                // A session in use, maybe waiting on a webhook, keeps what was saved before.
+                // Whoever is using it saves again once done
+                let session = match self.sessions.get(&id) {
+                    Some(session) => session,
+                    None => continue,
+                };
+                let session = match session.try_lock() {
+                    Ok(session) => session,
+                    Err(_) => continue,
+                };
+                let snapshot = SavedSession {
+                    flow: self.flow_name(&session).into(),
+                    snapshot: session.snapshot(),
+                };
+                saved.insert(id, snapshot);
+            }
+            // Write alongside and swap in, so a crash never leaves half a file
+            let temp = file.with_extension("tmp");
+            let result = serde_json::to_string(&*saved)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+                .and_then(|s| fs::write(&temp, s))
+                .and_then(|_| fs::rename(&temp, file));
+            if let Err(e) = result {
+                eprintln!("Failed to save sessions to {}: {}", file.display(), e);
+            }
+        }
+    }
+
+    /// JSON view of a session's current prompt
+    fn view(&self, id: &str, session: &Session) -> Value {
+        json!({
+            "id": id,
+            "flow": self.flow_name(session),
+            "locale": session.locale(),
+            "finished": session.is_finished(),
+            "node": if session.is_finished() { None } else { Some(session.current_node()) },
+            "prompt": if session.is_finished() { None } else { Some(session.current_prompt()) },
+        })
+    }
+
+    /// Route a request, returning the response to send
+    pub fn handle(&self, method: &str, url: &str, body: &str) -> Response {
+        if !self.sessions.expire().is_empty() {
+            self.save();
+        }
+        let path: Vec<&str> = url
+            .split('?')
+            .next()
+            .unwrap_or_default()
+            .split('/')
+            .filter(|p| !p.is_empty())
+            .collect();
+        match (method, path.as_slice()) {
+            ("GET", ["flows"]) => Response::new(
+                200,
+                json!({ "flows": self.flows.keys().collect::<Vec<_>>() }),
+            ),
+            ("POST", ["sessions"]) => self.create(body),
+            ("GET", ["sessions", id]) => self.show(id),
+            ("POST", ["sessions", id, "answer"]) => self.answer(id, body),
+            ("GET", ["sessions", id, "variables"]) => self.variables(id),
+            ("DELETE", ["sessions", id]) => match self.sessions.remove(id) {
+                Some(_) => {
+                    self.save();
+                    Response::new(204, Value::Null)
+                }
+                None => Response::error(404, "No such session"),
+            },
+            _ => Response::error(404, "Not found"),
+        }
+    }
+
+    /// `POST /sessions`
+    fn create(&self, body: &str) -> Response {
+        let request: CreateRequest = match serde_json::from_str(body) {
+            Ok(r) => r,
+            Err(e) => return Response::error(400, &e.to_string()),
+        };
+        let flow = match self.flows.get(&request.flow) {
+            Some(flow) => flow,
+            None => return Response::error(404, "No such flow"),
+        };
+        if let Some(locale) = &request.locale {
+            if !flow.catalogues().locales().contains(&locale.as_str()) {
+                return Response::error(400, "No such locale");
+            }
+        }
+        let id = self.sessions.create(flow);
+        let session = self.sessions.get(&id).expect("Should find new session");
+        let mut session = session.lock().expect("Should lock session");
+        session.set_locale(request.locale);
+        self.record_transcript(&id, &mut session);
+        // Starting a session shows its first prompt
+        session.prompt();
+        let body = self.view(&id, &session);
+        drop(session);
+        self.save();
+        Response::new(201, body)
+    }
+
+    /// `GET /sessions/<id>`
+    fn show(&self, id: &str) -> Response {
+        let session = match self.sessions.get(id) {
+            Some(s) => s,
+            None => return Response::error(404, "No such session"),
+        };
+        let mut session = session.lock().expect("Should lock session");
+        let changed = session.check_timeout().is_some();
+        if !session.is_finished() {
+            session.prompt();
+        }
+        let body = self.view(id, &session);
+        drop(session);
+        if changed {
+            self.save();
+        }
+        Response::new(200, body)
+    }
+
+    /// `POST /sessions/<id>/answer`
+    fn answer(&self, id: &str, body: &str) -> Response {
+        let request: AnswerRequest = match serde_json::from_str(body) {
+            Ok(r) => r,
+            Err(e) => return Response::error(400, &e.to_string()),
+        };
+        let session = match self.sessions.get(id) {
+            Some(s) => s,
+            None => return Response::error(404, "No such session"),
+        };
+        let mut session = session.lock().expect("Should lock session");
+        // A late answer goes to wherever the timeout sent the session
+        session.check_timeout();
+        if !session.is_finished() {
+            session.prompt();
+        }
+        let result = session.answer(&request.input);
+        if !session.is_finished() {
+            session.prompt();
+        }
+        let response = match result {
+            Ok(_) => Response::new(200, self.view(id, &session)),
+            Err(e) => {
+                let mut body = self.view(id, &session);
+                body["error"] = json!(e.to_string());
+                Response::new(422, body)
+            }
+        };
+        drop(session);
+        self.save();
+        response
+    }
+
+    /// `GET /sessions/<id>/variables`
+    fn variables(&self, id: &str) -> Response {
+        match self.sessions.get(id) {
+            Some(session) => {
+                let session = session.lock().expect("Should lock session");
+                let snapshot = session.snapshot();
+                Response::new(
+                    200,
+                    json!({
+                        "id": id,
+                        "flow": self.flow_name(&session),
+                        "finished": session.is_finished(),
+                        "path": snapshot.path,
+                        "variables": snapshot.variables,
+                    }),
+                )
+            }
+            None => Response::error(404, "No such session"),
+        }
+    }
+
+    /// Listen on `addr` and handle requests until the process ends
+    pub fn serve(self, addr: &str) -> io::Result<()> {
+        let server = tiny_http::Server::http(addr)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        let this = Arc::new(self);
+        for request in server.incoming_requests() {
+            let this = this.clone();
+            thread::spawn(move || this.respond(request));
+        }
+        Ok(())
+    }
+
+    /// Handle one request from `serve` and send the response
+    fn respond(&self, mut request: tiny_http::Request) {
+        let mut body = String::new();
+        let response = match request.as_reader().read_to_string(&mut body) {
+            Ok(_) => self.handle(
+                &request.method().to_string().to_uppercase(),
+                request.url(),
+                &body,
+            ),
+            Err(e) => Response::error(400, &e.to_string()),
+        };
+        let text = if response.body.is_null() {
+            String::new()
+        } else {
+            response.body.to_string()
+        };
+        let header = tiny_http::Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+            .expect("Should build header");
+        let reply = tiny_http::Response::from_string(text)
+            .with_status_code(response.status)
+            .with_header(header);
+        if let Err(e) = request.respond(reply) {
+            eprintln!("Failed to respond: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// Server with the bundled demo flow
+    fn server() -> Server {
+        let mut ret = Server::default();
+        ret.add_flow("input", include_str!("../input.txt").parse().unwrap());
+        ret
+    }
+
+    /// Server with both bundled flows, the second with its catalogues
+    fn localised_server() -> Server {
+        let mut ret = server();
+        ret.add_flow("AdReadiness", Flow::from_file("AdReadiness.txt").unwrap());
+        ret
+    }
+
+    #[test]
+    fn test_session_lifecycle() {
+        let server = server();
+        assert_eq!(
+            server.handle("GET", "/flows", "").body,
+            json!({ "flows": ["input"] })
+        );
+        assert_eq!(
+            server
+                .handle("POST", "/sessions", r#"{"flow": "nope"}"#)
+                .status,
+            404
+        );
+        let created = server.handle("POST", "/sessions", r#"{"flow": "input"}"#);
+        assert_eq!(created.status, 201);
+        let id = created.body["id"].as_str().unwrap().to_string();
+        assert_eq!(
+            created.body["prompt"],
+            json!({"kind": "question", "text": "What is your name?", "attempt": 0, "attempts": 3})
+        );
+        let answer = |server: &Server, input: &str| {
+            server.handle(
+                "POST",
+                &format!("/sessions/{}/answer", id),
+                &json!({ "input": input }).to_string(),
+            )
+        };
+        assert_eq!(answer(&server, "Arthur").status, 200);
+        let rejected = answer(&server, "7");
+        assert_eq!(rejected.status, 422);
+        assert_eq!(rejected.body["error"], json!("Not a valid option!"));
+        assert_eq!(
+            rejected.body["prompt"]["options"],
+            json!(["The Holy Grail", "Run and Hide"])
+        );
+        answer(&server, "2");
+        let shown = server.handle("GET", &format!("/sessions/{}", id), "");
+        assert_eq!(shown.body["prompt"]["kind"], json!("terminating"));
+        answer(&server, "");
+        let variables = server.handle("GET", &format!("/sessions/{}/variables", id), "");
+        assert_eq!(
+            variables.body,
+            json!({
+                "id": id,
+                "flow": "input",
+                "finished": true,
+                "path": [0, 1, 3],
+                "variables": {"NAME": "Arthur", "QUEST": "Run and Hide"},
+            })
+        );
+        assert_eq!(
+            server
+                .handle("DELETE", &format!("/sessions/{}", id), "")
+                .status,
+            204
+        );
+        assert_eq!(server.session_count(), 0);
+    }
+
+    #[test]
+    fn test_persistence() {
+        let file = std::env::temp_dir().join(format!("winnow-state-{}.json", std::process::id()));
+        let _ = fs::remove_file(&file);
+        let mut first = localised_server();
+        first.set_state_file(file.clone()).unwrap();
+        let id = first
+            .handle("POST", "/sessions", r#"{"flow": "input"}"#)
+            .body["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        first.handle(
+            "POST",
+            &format!("/sessions/{}/answer", id),
+            r#"{"input": "Lancelot"}"#,
+        );
+        assert_eq!(
+            first
+                .handle(
+                    "POST",
+                    "/sessions",
+                    r#"{"flow": "AdReadiness", "locale": "fr"}"#
+                )
+                .status,
+            400
+        );
+        let spanish = first.handle(
+            "POST",
+            "/sessions",
+            r#"{"flow": "AdReadiness", "locale": "es"}"#,
+        );
+        assert_eq!(
+            spanish.body["prompt"]["options"],
+            json!(["Empezar", "No estoy seguro"])
+        );
+        let spanish = spanish.body["id"].as_str().unwrap().to_string();
+        let mut second = localised_server();
+        second.set_state_file(file.clone()).unwrap();
+        let shown = second.handle("GET", &format!("/sessions/{}", id), "");
+        assert_eq!(
+            shown.body["prompt"]["text"],
+            json!("Lancelot, what is your quest?")
+        );
+        let shown = second.handle("GET", &format!("/sessions/{}", spanish), "");
+        assert_eq!(shown.body["locale"], json!("es"));
+        assert_eq!(shown.body["prompt"]["options"][0], json!("Empezar"));
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_save_busy_session() {
+        let file = std::env::temp_dir().join(format!("winnow-busy-{}.json", std::process::id()));
+        let _ = fs::remove_file(&file);
+        let mut server = server();
+        server.set_state_file(file.clone()).unwrap();
+        let create = || {
+            server
+                .handle("POST", "/sessions", r#"{"flow": "input"}"#)
+                .body["id"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        };
+        let busy = create();
+        let session = server.sessions.get(&busy).unwrap();
+        let guard = session.lock().unwrap();
+        // Saving doesn't wait for a session in use, it keeps what was saved of it before
+        let idle = create();
+        let saved: HashMap<String, SavedSession> =
+            serde_json::from_str(&fs::read_to_string(&file).unwrap()).unwrap();
+        assert!(saved.contains_key(&busy) && saved.contains_key(&idle));
+        drop(guard);
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_slow_webhook() {
+        use std::{net::TcpListener, sync::mpsc, time::Instant};
+        // Accepts connections but never answers
+        let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+        let flow = format!(
+            "1\n1\n1\nNAME\nWhat is your name?\n/* */\n6\nhttp://{}/hook\n2\n2\ntimeout 5\n3\nSent\n",
+            silent.local_addr().unwrap()
+        );
+        let mut server = Server::default();
+        server.add_flow("hook", flow.parse().unwrap());
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        thread::spawn(move || server.serve(&addr.to_string()));
+        let base = format!("http://{}", addr);
+        let created: Value = loop {
+            match ureq::post(&format!("{}/sessions", base)).send_string(r#"{"flow": "hook"}"#) {
+                Ok(response) => break response.into_json().unwrap(),
+                Err(_) => thread::sleep(Duration::from_millis(10)),
+            }
+        };
+        let answer = format!(
+            "{}/sessions/{}/answer",
+            base,
+            created["id"].as_str().unwrap()
+        );
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = ureq::post(&answer).send_string(r#"{"input": "Arthur"}"#);
+            tx.send(()).unwrap();
+        });
+        thread::sleep(Duration::from_millis(200));
+        // Still waiting on the webhook, and everything else carries on
+        assert!(rx.try_recv().is_err());
+        let started = Instant::now();
+        let flows: Value = ureq::get(&format!("{}/flows", base))
+            .call()
+            .unwrap()
+            .into_json()
+            .unwrap();
+        assert_eq!(flows, json!({ "flows": ["hook"] }));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,345 @@
+//! # simulate
+//! Drives a flow through the step API with random answers, many times over, to shake out problems
+//! no hand-written scenario thought of: panics, sessions that never reach a terminating node,
+//! and prompts that show a bare variable name because it was never set.
+//!
+//! Branch options are chosen uniformly unless weighted, questions are sometimes left blank
+//! to exhaust their prompts, and some answers are deliberately invalid.
+//! Pass a seeded RNG to make a run repeatable. Webhook nodes post nothing and always succeed.
+
+use rand::{
+    distributions::{Alphanumeric, WeightedIndex},
+    prelude::*,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+};
+
+use crate::{webhook::StubTransport, ManualClock, NodeId, Nodes, Prompt};
+
+/// Stand-in for a timeout in the list of inputs
+const TIMEOUT_INPUT: &str = "(timeout)";
+
+/// Simulation settings
+#[derive(Clone, Debug)]
+pub struct Simulation {
+    /// Number of simulated users
+    pub sessions: usize,
+    /// Answers a session may give before it's considered stuck in a loop
+    pub max_steps: usize,
+    /// Chance of leaving a question blank
+    pub blank_chance: f64,
+    /// Chance of an invalid answer to a branching node
+    pub garbage_chance: f64,
+    /// Chance of letting a prompt time out, where it has a timeout
+    pub timeout_chance: f64,
+    /// Relative weight of each option, by node. Unlisted nodes choose uniformly
+    pub weights: HashMap<NodeId, Vec<u32>>,
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Self {
+            sessions: 1000,
+            max_steps: 1000,
+            blank_chance: 0.2,
+            garbage_chance: 0.1,
+            timeout_chance: 0.05,
+            weights: HashMap::new(),
+        }
+    }
+}
+
+/// A session that went wrong, with what it took to get there
+#[derive(Clone, Debug, PartialEq)]
+pub struct Failure {
+    /// Which session, counting from 0
+    pub session: usize,
+    /// What went wrong
+    pub message: String,
+    /// Every answer given, in order
+    pub inputs: Vec<String>,
+    /// The last few nodes entered
+    pub path_tail: Vec<NodeId>,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        /// Answers shown per failure
+        const SHOWN: usize = 20;
+        let tail: Vec<String> = self.path_tail.iter().map(|n| n.to_string()).collect();
+        let shown: Vec<&str> = self
+            .inputs
+            .iter()
+            .take(SHOWN)
+            .map(|i| if i.is_empty() { "(blank)" } else { i.as_str() })
+            .collect();
+        write!(
+            f,
+            "session {}: {}\n    last nodes: {}\n    inputs: {}{}",
+            self.session,
+            self.message,
+            tail.join(" -> "),
+            shown.join(", "),
+            if self.inputs.len() > SHOWN {
+                ", ..."
+            } else {
+                ""
+            }
+        )
+    }
+}
+
+/// What the simulated users got up to
+#[derive(Debug, Default)]
+pub struct SimulationReport {
+    /// Sessions simulated
+    pub sessions: usize,
+    /// Sessions ending at each terminating node
+    pub terminated: BTreeMap<NodeId, usize>,
+    /// Sessions that hit the step limit
+    pub loops: Vec<Failure>,
+    /// Sessions that panicked
+    pub panics: Vec<Failure>,
+    /// Times a prompt at a node showed an unset variable
+    pub unresolved: BTreeMap<(NodeId, String), usize>,
+    /// Answers given across every session
+    pub total_steps: usize,
+}
+
+impl SimulationReport {
+    /// Whether nothing went wrong
+    pub fn is_clean(&self) -> bool {
+        self.loops.is_empty() && self.panics.is_empty() && self.unresolved.is_empty()
+    }
+}
+
+impl fmt::Display for SimulationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        /// Failures shown per kind
+        const SHOWN: usize = 5;
+        writeln!(
+            f,
+            "Sessions: {}, average {:.1} answers each",
+            self.sessions,
+            self.total_steps as f64 / self.sessions.max(1) as f64
+        )?;
+        writeln!(f, "Terminating nodes reached:")?;
+        for (node, count) in &self.terminated {
+            writeln!(
+                f,
+                "  node {:>4}: {:>6} ({:.1}%)",
+                node,
+                count,
+                *count as f64 * 100.0 / self.sessions.max(1) as f64
+            )?;
+        }
+        writeln!(f, "Stuck in a loop: {}", self.loops.len())?;
+        for failure in self.loops.iter().take(SHOWN) {
+            writeln!(f, "  {}", failure)?;
+        }
+        writeln!(f, "Panicked: {}", self.panics.len())?;
+        for failure in self.panics.iter().take(SHOWN) {
+            writeln!(f, "  {}", failure)?;
+        }
+        writeln!(f, "Unresolved variables: {}", self.unresolved.len())?;
+        for ((node, name), count) in &self.unresolved {
+            writeln!(f, "  node {}: ${} shown {} times", node, name, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Turn a panic payload into something printable
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "panicked".into()
+    }
+}
+
+impl Simulation {
+    /// Simulate `sessions` users, each against a fresh machine from `make`
+    pub fn run<R: Rng, F: Fn() -> Nodes>(&self, make: F, rng: &mut R) -> SimulationReport {
+        let mut report = SimulationReport {
+            sessions: self.sessions,
+            ..Default::default()
+        };
+        for session in 0..self.sessions {
+            let mut inputs = Vec::new();
+            let mut tail = Vec::new();
+            let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+                self.session(make(), rng, &mut inputs, &mut tail, &mut report)
+            }));
+            report.total_steps += inputs.len();
+            let failure = |message: String| Failure {
+                session,
+                message,
+                inputs: inputs.clone(),
+                path_tail: tail.clone(),
+            };
+            match outcome {
+                Ok(Some(terminal)) => *report.terminated.entry(terminal).or_default() += 1,
+                Ok(None) => report.loops.push(failure(format!(
+                    "no terminating node within {} answers",
+                    self.max_steps
+                ))),
+                Err(payload) => report.panics.push(failure(panic_message(payload))),
+            }
+        }
+        report
+    }
+
+    /// Run one session, returning the terminating node reached or `None` if it ran out of steps.
+    /// `inputs` and `tail` are kept up to date as it goes, in case it panics.
+    fn session<R: Rng>(
+        &self,
+        mut nodes: Nodes,
+        rng: &mut R,
+        inputs: &mut Vec<String>,
+        tail: &mut Vec<NodeId>,
+        report: &mut SimulationReport,
+    ) -> Option<NodeId> {
+        /// Nodes kept in the tail
+        const TAIL: usize = 10;
+        let clock = Arc::new(ManualClock::default());
+        nodes.set_clock(clock.clone());
+        // Split nodes draw from the simulation's randomness, so a seed repeats the whole run
+        nodes.set_seed(rng.gen());
+        nodes.set_webhook_transport(Arc::new(StubTransport::default()));
+        while inputs.len() < self.max_steps {
+            let prompt = nodes.prompt();
+            tail.clear();
+            tail.extend(nodes.path().iter().rev().take(TAIL).rev());
+            for name in nodes.unresolved_variables() {
+                *report
+                    .unresolved
+                    .entry((nodes.current_node(), name))
+                    .or_default() += 1;
+            }
+            if let Prompt::Terminating { .. } = prompt {
+                return Some(nodes.current_node());
+            }
+            // Sometimes just walk away
+            if let Some(timeout) = nodes.current_timeout() {
+                if rng.gen_bool(self.timeout_chance) {
+                    inputs.push(TIMEOUT_INPUT.into());
+                    clock.advance(timeout.after);
+                    nodes.check_timeout();
+                    continue;
+                }
+            }
+            let input = self.choose(&nodes, &prompt, rng);
+            inputs.push(input.clone());
+            // Rejected answers are expected, the same prompt just shows again
+            let _ = nodes.answer(&input);
+            if nodes.is_finished() {
+                return nodes.path().last().cloned();
+            }
+        }
+        None
+    }
+
+    /// Pick an answer for the current prompt
+    fn choose<R: Rng>(&self, nodes: &Nodes, prompt: &Prompt, rng: &mut R) -> String {
+        match prompt {
+            Prompt::Branching { options, .. } => {
+                if rng.gen_bool(self.garbage_chance) {
+                    return match rng.gen_range(0..4) {
+                        0 => String::new(),
+                        1 => "0".into(),
+                        2 => (options.len() + 1).to_string(),
+                        _ => random_string(rng),
+                    };
+                }
+                let weighted = self
+                    .weights
+                    .get(&nodes.current_node())
+                    .filter(|w| w.len() == options.len())
+                    .and_then(|w| WeightedIndex::new(w).ok());
+                let choice = match weighted {
+                    Some(dist) => dist.sample(rng),
+                    None => rng.gen_range(0..options.len().max(1)),
+                };
+                (choice + 1).to_string()
+            }
+            Prompt::Question { .. } => {
+                if rng.gen_bool(self.blank_chance) {
+                    String::new()
+                } else {
+                    random_string(rng)
+                }
+            }
+            Prompt::Terminating { .. } => String::new(),
+        }
+    }
+}
+
+/// Short random alphanumeric string
+fn random_string<R: Rng>(rng: &mut R) -> String {
+    let len = rng.gen_range(1..=12);
+    rng.sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn test_simulate_input() {
+        let mut rng = StdRng::seed_from_u64(26);
+        let simulation = Simulation {
+            sessions: 200,
+            ..Default::default()
+        };
+        let report = simulation.run(|| include_str!("../input.txt").parse().unwrap(), &mut rng);
+        assert!(report.loops.is_empty());
+        assert!(report.panics.is_empty());
+        assert_eq!(report.terminated.values().sum::<usize>(), 200);
+        assert_eq!(
+            report.terminated.keys().cloned().collect::<Vec<_>>(),
+            vec![3, 4, 5]
+        );
+        assert!(report.unresolved.is_empty());
+    }
+
+    #[test]
+    fn test_simulate_failures() {
+        let mut rng = StdRng::seed_from_u64(26);
+        let simulation = Simulation {
+            sessions: 50,
+            max_steps: 30,
+            weights: vec![(1, vec![0, 1])].into_iter().collect(),
+            ..Default::default()
+        };
+        // Node 1 loops on itself, node 2 points nowhere
+        let flow =
+            "1\n1\n2\nNAME\nName?\n/* */\n2\nLOOP\n$NAME, again $COLOR?\nBad:7\nAgain:1\n3\nGone $NAME\n";
+        let report = simulation.run(|| flow.parse().unwrap(), &mut rng);
+        // Weighted to never choose the bad option, and never stop looping
+        assert!(report.panics.is_empty());
+        assert!(report.terminated.keys().all(|n| *n == 2));
+        assert!(!report.loops.is_empty());
+        assert!(report.unresolved.contains_key(&(1, "COLOR".into())));
+        assert!(report.unresolved.contains_key(&(2, "NAME".into())));
+        // Without weights, the bad option panics
+        let report = Simulation {
+            weights: HashMap::new(),
+            ..simulation
+        }
+        .run(|| flow.parse().unwrap(), &mut rng);
+        assert!(!report.panics.is_empty());
+        assert!(!report.is_clean());
+    }
+}
@@ -0,0 +1,177 @@
+//! # split
+//! A split node asks nothing. It picks one of its variants at random by weight, records the variant's name
+//! in its variable, and moves on to the variant's destination, so two wordings of a prompt can be tried side by side:
+//! ```txt
+//! 5 // Node type must be 5
+//! GREETING // Variable to record the chosen variant in
+//! Formal:1 weight 3 // Variant name:destination, taken 3 times in 4
+//! Casual:2 // A weight of 1 if left out
+//! ```
+//! A session whose variable already holds one of the variant names keeps that variant,
+//! which is how [`replay`](../replay/index.html) takes the same one as the recording.
+//!
+//! Variants are drawn with [`Nodes::set_rng`](../struct.Nodes.html#method.set_rng), or a seeded generator from
+//! [`Nodes::set_seed`](../struct.Nodes.html#method.set_seed) when a run has to be repeatable.
+//! [`variant_outcomes`](fn.variant_outcomes.html) tallies how the sessions given each variant ended.
+
+use rand::{rngs::StdRng, Rng, RngCore, SeedableRng};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    transcript::{Cause, Entry, Event},
+    NodeId, NodeType, Nodes,
+};
+
+/// Shared handle to a random number generator, so `Nodes` can keep its derives
+#[derive(Clone)]
+pub(crate) struct SharedRng(Arc<Mutex<dyn RngCore + Send>>);
+
+impl SharedRng {
+    /// Wrap a generator
+    pub(crate) fn new(rng: impl RngCore + Send + 'static) -> Self {
+        Self(Arc::new(Mutex::new(rng)))
+    }
+
+    /// A number from 0 up to but not including `end`, which must not be 0
+    pub(crate) fn gen_range(&self, end: u64) -> u64 {
+        self.0.lock().expect("Should lock rng").gen_range(0..end)
+    }
+}
+
+impl Default for SharedRng {
+    fn default() -> Self {
+        Self::new(StdRng::from_entropy())
+    }
+}
+
+impl fmt::Debug for SharedRng {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SharedRng(..)")
+    }
+}
+
+// Two machines don't differ just because they draw variants differently
+impl PartialEq for SharedRng {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+/// How the sessions given one variant ended
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct VariantOutcome {
+    /// Sessions given this variant
+    pub sessions: usize,
+    /// Sessions reaching each terminating node
+    pub terminated: BTreeMap<NodeId, usize>,
+    /// Sessions that stopped before reaching one
+    pub unfinished: usize,
+}
+
+/// Tally how the sessions in `transcripts` ended, by the variant split node `split` gave them.
+/// Sessions that never passed through it aren't counted.
+pub fn variant_outcomes(
+    nodes: &Nodes,
+    split: NodeId,
+    transcripts: &[Vec<Entry>],
+) -> BTreeMap<String, VariantOutcome> {
+    let mut ret: BTreeMap<String, VariantOutcome> = BTreeMap::new();
+    let variable = match nodes.flow.nodes.get(split) {
+        Some(node) => match (&node.node_type, &node.variable) {
+            (NodeType::Split(_), Some(variable)) => variable,
+            _ => return ret,
+        },
+        None => return ret,
+    };
+    for entries in transcripts {
+        // The variant is recorded just before the split moves on
+        let mut recorded = None;
+        let mut variant = None;
+        let mut terminated = None;
+        for entry in entries {
+            match &entry.event {
+                Event::VariableSet { name, value } if name == variable => {
+                    recorded = Some(value.clone())
+                }
+                Event::Transition {
+                    from,
+                    cause: Cause::Split,
+                    ..
+                } if *from == split && variant.is_none() => variant = recorded.clone(),
+                // A transcript of some other flow can name nodes this one doesn't have
+                Event::Transition { from, to: None, .. }
+                    if matches!(
+                        nodes.flow.nodes.get(*from).map(|n| &n.node_type),
+                        Some(NodeType::Terminating(_))
+                    ) =>
+                {
+                    terminated = Some(*from)
+                }
+                _ => {}
+            }
+        }
+        if let Some(variant) = variant {
+            let outcome = ret.entry(variant).or_default();
+            outcome.sessions += 1;
+            match terminated {
+                Some(node) => *outcome.terminated.entry(node).or_default() += 1,
+                None => outcome.unfinished += 1,
+            }
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transcript::MemorySink;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_variant_outcomes() {
+        let flow = "5\nGREETING\nFormal:1\nCasual:2\n3\nGood day\n3\nHey\n";
+        let nodes: Nodes = flow.parse().unwrap();
+        let mut transcripts = Vec::new();
+        for seed in 0..20 {
+            let sink = MemorySink::default();
+            let mut session: Nodes = flow.parse().unwrap();
+            session.set_transcript(Some(Box::new(sink.clone())));
+            session.set_seed(seed);
+            session.prompt();
+            // Every other session walks away from the exit message
+            if seed % 2 == 0 {
+                session.answer("").unwrap();
+            }
+            transcripts.push(sink.entries());
+        }
+        let outcomes = variant_outcomes(&nodes, 0, &transcripts);
+        assert_eq!(
+            outcomes.keys().collect::<Vec<_>>(),
+            vec!["Casual", "Formal"]
+        );
+        let formal = &outcomes["Formal"];
+        let casual = &outcomes["Casual"];
+        assert_eq!(formal.sessions + casual.sessions, 20);
+        assert_eq!(formal.unfinished + casual.unfinished, 10);
+        assert_eq!(
+            formal.terminated.get(&1).unwrap_or(&0) + casual.terminated.get(&2).unwrap_or(&0),
+            10
+        );
+        assert_eq!(variant_outcomes(&nodes, 1, &transcripts), BTreeMap::new());
+        // Recorded against a longer flow
+        let mut foreign = transcripts[0].clone();
+        foreign.push(Entry::now(Event::Transition {
+            from: 42,
+            to: None,
+            cause: Cause::Answer,
+        }));
+        let outcomes = variant_outcomes(&nodes, 0, &[foreign]);
+        assert_eq!(outcomes.values().map(|o| o.sessions).sum::<usize>(), 1);
+    }
+}
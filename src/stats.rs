@@ -0,0 +1,360 @@
+//! # stats
+//! [`Stats`](struct.Stats.html) tallies many session [transcripts](../transcript/index.html) against their flow:
+//! how many sessions reached each node and where they went next, where they stopped,
+//! how many prompts a question took on average before it got an answer, and which branch options were chosen.
+//! It prints as a table, or serializes to JSON for other tools.
+
+use serde::Serialize;
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    sync::Arc,
+};
+
+use crate::{
+    analysis::{Coverage, EdgeKind},
+    transcript::{Entry, Event},
+    Flow, NodeId, NodeType, Nodes,
+};
+
+/// How sessions used one node
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct NodeStats {
+    /// Sessions that entered it
+    pub reached: usize,
+    /// Times execution moved on from it, by destination
+    pub next: BTreeMap<NodeId, usize>,
+    /// Sessions that stopped here without finishing
+    pub dropped: usize,
+    /// Sessions that finished here, or were left at its exit message
+    pub finished: usize,
+    /// Question nodes: prompts shown per visit, on average. Above 1 means blank answers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub average_attempts: Option<f64>,
+    /// Branching nodes: options in the order chosen most
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<OptionChoice>,
+    /// Visits that showed a prompt, for the average
+    #[serde(skip)]
+    visits: usize,
+    /// Prompts shown across those visits
+    #[serde(skip)]
+    attempts: usize,
+}
+
+/// How often a branch option was chosen
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct OptionChoice {
+    /// Option number in the flow file, counting from 1
+    pub option: usize,
+    /// Option text, in the flow's default locale
+    pub text: String,
+    /// Times chosen
+    pub chosen: usize,
+}
+
+/// A node where sessions ended
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Exit {
+    /// Last node
+    pub node: NodeId,
+    /// Whether the sessions finished there, rather than stopping
+    pub finished: bool,
+    /// Sessions that ended there
+    pub sessions: usize,
+}
+
+/// Totals across recorded sessions of one flow
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    /// Sessions recorded
+    pub sessions: usize,
+    /// Every node in the flow
+    pub nodes: BTreeMap<NodeId, NodeStats>,
+    /// Where sessions ended, most common first
+    pub exits: Vec<Exit>,
+    /// Decides which option a branching answer chose
+    #[serde(skip)]
+    coverage: Coverage,
+    /// The flow the sessions ran
+    #[serde(skip)]
+    flow: Arc<Flow>,
+}
+
+impl Stats {
+    /// Start with every node in the flow at zero
+    pub fn new(nodes: &Nodes) -> Self {
+        Self {
+            sessions: 0,
+            nodes: (0..nodes.flow.len())
+                .map(|n| (n, NodeStats::default()))
+                .collect(),
+            exits: Vec::new(),
+            coverage: Coverage::new(nodes),
+            flow: nodes.flow.clone(),
+        }
+    }
+
+    /// Tally one session's transcript
+    pub fn record(&mut self, entries: &[Entry]) {
+        self.sessions += 1;
+        self.coverage.record(entries);
+        let mut reached = BTreeSet::new();
+        let mut last = None;
+        let mut ended = None;
+        // Question being visited, whether its prompt was shown, and blank answers so far
+        let mut visit: Option<(NodeId, bool, usize)> = None;
+        for entry in entries {
+            match &entry.event {
+                Event::NodeEntered { node } => {
+                    self.close_visit(visit.take());
+                    reached.insert(*node);
+                    last = Some(*node);
+                    if let Some(NodeType::Question(..)) =
+                        self.flow.nodes.get(*node).map(|n| &n.node_type)
+                    {
+                        visit = Some((*node, false, 0));
+                    }
+                }
+                Event::Prompted { node, .. } => match &mut visit {
+                    Some((n, shown, _)) if n == node => *shown = true,
+                    _ => {}
+                },
+                Event::Input { node, raw } if raw.is_empty() => match &mut visit {
+                    Some((n, _, blanks)) if n == node => *blanks += 1,
+                    _ => {}
+                },
+                Event::Transition { from, to, .. } => match to {
+                    Some(to) => *self.node(*from).next.entry(*to).or_default() += 1,
+                    None => ended = Some(*from),
+                },
+                _ => {}
+            }
+        }
+        self.close_visit(visit);
+        for node in reached {
+            self.node(node).reached += 1;
+        }
+        // Left at an exit message still counts as finishing
+        match ended.or(last) {
+            Some(node) if ended.is_some() || self.is_terminating(node) => {
+                self.node(node).finished += 1
+            }
+            Some(node) => self.node(node).dropped += 1,
+            None => {}
+        }
+        self.summarise();
+    }
+
+    /// Whether a node shows an exit message
+    fn is_terminating(&self, node: NodeId) -> bool {
+        matches!(
+            self.flow.nodes.get(node).map(|n| &n.node_type),
+            Some(NodeType::Terminating(_))
+        )
+    }
+
+    /// Stats for a node, even one the flow doesn't have
+    fn node(&mut self, node: NodeId) -> &mut NodeStats {
+        self.nodes.entry(node).or_default()
+    }
+
+    /// Count the prompts a question visit used, up to the number it has
+    fn close_visit(&mut self, visit: Option<(NodeId, bool, usize)>) {
+        if let Some((node, true, blanks)) = visit {
+            let prompts = match &self.flow.nodes[node].node_type {
+                NodeType::Question(_, _, qs) => qs.len(),
+                _ => 1,
+            };
+            let stats = self.node(node);
+            stats.visits += 1;
+            stats.attempts += (1 + blanks).min(prompts);
+        }
+    }
+
+    /// Work out the averages, option choices and exits from the totals
+    fn summarise(&mut self) {
+        for stats in self.nodes.values_mut() {
+            stats.average_attempts = match stats.visits {
+                0 => None,
+                visits => Some(stats.attempts as f64 / visits as f64),
+            };
+            stats.options.clear();
+        }
+        for (edge, chosen) in &self.coverage.edges {
+            if let (EdgeKind::Option(option), true) = (edge.kind, *chosen > 0) {
+                let text = match &self.flow.nodes[edge.from].node_type {
+                    NodeType::Branching(_, options) => self
+                        .flow
+                        .catalogues
+                        .localise(None, &options[option - 1].text),
+                    _ => "",
+                };
+                if let Some(stats) = self.nodes.get_mut(&edge.from) {
+                    stats.options.push(OptionChoice {
+                        option,
+                        text: text.into(),
+                        chosen: *chosen,
+                    });
+                }
+            }
+        }
+        self.exits.clear();
+        for (node, stats) in &mut self.nodes {
+            stats.options.sort_by_key(|o| Reverse(o.chosen));
+            for (finished, sessions) in [(true, stats.finished), (false, stats.dropped)] {
+                if sessions > 0 {
+                    self.exits.push(Exit {
+                        node: *node,
+                        finished,
+                        sessions,
+                    });
+                }
+            }
+        }
+        self.exits.sort_by_key(|e| Reverse(e.sessions));
+    }
+
+    /// Pretty-printed JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Should serialize stats")
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        /// Share of `total`, as a whole percentage
+        fn percent(count: usize, total: usize) -> usize {
+            (count * 100).checked_div(total).unwrap_or(0)
+        }
+        writeln!(f, "Sessions: {}\n", self.sessions)?;
+        writeln!(
+            f,
+            "{:>5}  {:>7}  {:>7}  {:>8}  {:>8}",
+            "Node", "Reached", "Dropped", "Finished", "Attempts"
+        )?;
+        for (node, stats) in &self.nodes {
+            let attempts = stats
+                .average_attempts
+                .map(|a| format!("{:.2}", a))
+                .unwrap_or_else(|| "-".into());
+            writeln!(
+                f,
+                "{:>5}  {:>7}  {:>7}  {:>8}  {:>8}",
+                node, stats.reached, stats.dropped, stats.finished, attempts
+            )?;
+        }
+        writeln!(f, "\nDrop-off between nodes:")?;
+        for (node, stats) in &self.nodes {
+            for (to, count) in &stats.next {
+                writeln!(
+                    f,
+                    "  {:>4} -> {:<4}  {:>5}  {:>3}% of those reaching {}",
+                    node,
+                    to,
+                    count,
+                    percent(*count, stats.reached),
+                    node
+                )?;
+            }
+        }
+        writeln!(f, "\nMost common exits:")?;
+        for exit in &self.exits {
+            writeln!(
+                f,
+                "  {:>5}  node {} ({})",
+                exit.sessions,
+                exit.node,
+                if exit.finished { "finished" } else { "dropped" }
+            )?;
+        }
+        writeln!(f, "\nMost chosen options:")?;
+        for (node, stats) in &self.nodes {
+            for choice in &stats.options {
+                writeln!(
+                    f,
+                    "  {:>5}  node {} option {}: {}",
+                    choice.chosen, node, choice.option, choice.text
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transcript::MemorySink;
+    use pretty_assertions::assert_eq;
+
+    /// Record a session answering `inputs` against `flow`
+    fn record(flow: &str, inputs: &[&str]) -> Vec<Entry> {
+        let sink = MemorySink::default();
+        let mut nodes: Nodes = flow.parse().unwrap();
+        nodes.set_transcript(Some(Box::new(sink.clone())));
+        for input in inputs {
+            nodes.prompt();
+            let _ = nodes.answer(input);
+        }
+        sink.entries()
+    }
+
+    #[test]
+    fn test_stats() {
+        let flow = include_str!("../input.txt");
+        let mut stats = Stats::new(&flow.parse().unwrap());
+        stats.record(&record(flow, &["", "Arthur", "1", "2", ""]));
+        stats.record(&record(flow, &["Robin", "2", ""]));
+        stats.record(&record(flow, &["Lancelot", "1"]));
+        stats.record(&record(flow, &["", "", ""]));
+        assert_eq!(stats.sessions, 4);
+        let first = &stats.nodes[&0];
+        assert_eq!(first.reached, 4);
+        assert_eq!(first.next, vec![(1, 3), (3, 1)].into_iter().collect());
+        // Two prompts, one, one, and all three
+        assert_eq!(first.average_attempts, Some(7.0 / 4.0));
+        assert_eq!(
+            stats.nodes[&1].options,
+            vec![
+                OptionChoice {
+                    option: 1,
+                    text: "The Holy Grail".into(),
+                    chosen: 2
+                },
+                OptionChoice {
+                    option: 2,
+                    text: "Run and Hide".into(),
+                    chosen: 1
+                },
+            ]
+        );
+        assert_eq!(
+            stats.exits,
+            vec![
+                Exit {
+                    node: 3,
+                    finished: true,
+                    sessions: 2
+                },
+                Exit {
+                    node: 2,
+                    finished: false,
+                    sessions: 1
+                },
+                Exit {
+                    node: 5,
+                    finished: true,
+                    sessions: 1
+                },
+            ]
+        );
+        let json: serde_json::Value = serde_json::from_str(&stats.to_json()).unwrap();
+        assert_eq!(json["nodes"]["1"]["options"][0]["chosen"], 2);
+        assert_eq!(json["nodes"]["1"].get("average_attempts"), None);
+        assert!(stats
+            .to_string()
+            .contains("      2  node 3 (finished)\n      1  node 2 (dropped)"));
+    }
+}
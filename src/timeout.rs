@@ -0,0 +1,164 @@
+//! # timeout
+//! A prompt can be given a deadline, either globally with [`Nodes::set_timeout`](../struct.Nodes.html#method.set_timeout)
+//! or per node with an `@timeout` directive in the input file.
+//! When the deadline passes the machine either jumps to a destination node or shows the prompt again.
+//!
+//! Time is read through the [`Clock`](trait.Clock.html) trait so tests and embedding front ends can supply their own.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::NodeId;
+
+/// Source of the current time
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Current instant
+    fn now(&self) -> Instant;
+}
+
+/// Wall clock, the default
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Clock that only moves when told to
+/// # Example
+///
+/// ```
+/// # use winnow_sm::{Clock, ManualClock};
+/// # use std::time::Duration;
+/// let clock = ManualClock::default();
+/// let start = clock.now();
+/// clock.advance(Duration::from_secs(30));
+/// assert_eq!(clock.now() - start, Duration::from_secs(30));
+/// ```
+#[derive(Debug)]
+pub struct ManualClock(Mutex<Instant>);
+
+impl ManualClock {
+    /// Move the clock forward
+    pub fn advance(&self, by: Duration) {
+        let mut now = self.0.lock().expect("Should lock clock");
+        *now += by;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self(Mutex::new(Instant::now()))
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.0.lock().expect("Should lock clock")
+    }
+}
+
+/// Shared handle to a clock, so `Nodes` can keep its derives
+#[derive(Clone, Debug)]
+pub(crate) struct SharedClock(pub(crate) Arc<dyn Clock>);
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        Self(Arc::new(SystemClock))
+    }
+}
+
+// Two machines don't differ just because they tell time differently
+impl PartialEq for SharedClock {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+/// What to do once a prompt has gone unanswered too long
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeoutAction {
+    /// Jump to this node
+    Goto(NodeId),
+    /// Show the same prompt again and restart the timer
+    Reprompt,
+}
+
+/// A deadline for answering a prompt
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timeout {
+    /// How long to wait for input
+    pub after: Duration,
+    /// What to do when time runs out
+    pub action: TimeoutAction,
+}
+
+impl Timeout {
+    /// Construct a fresh timeout
+    pub fn new(after: Duration, action: TimeoutAction) -> Self {
+        Self { after, action }
+    }
+}
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.action {
+            TimeoutAction::Goto(node) => write!(f, "{}:{}", self.after.as_secs(), node),
+            TimeoutAction::Reprompt => write!(f, "{}:reprompt", self.after.as_secs()),
+        }
+    }
+}
+
+impl std::str::FromStr for Timeout {
+    type Err = String;
+
+    /// Parse `<seconds>:<node>` or `<seconds>:reprompt`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        let secs = parts
+            .next()
+            .unwrap_or_default()
+            .parse::<u64>()
+            .map_err(|e| format!("Bad timeout seconds in {}: {}", s, e))?;
+        let action = match parts.next() {
+            Some("reprompt") => TimeoutAction::Reprompt,
+            Some(node) => TimeoutAction::Goto(
+                node.parse::<NodeId>()
+                    .map_err(|e| format!("Bad timeout destination in {}: {}", s, e))?,
+            ),
+            None => return Err(format!("Timeout {} needs a destination", s)),
+        };
+        Ok(Self::new(Duration::from_secs(secs), action))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_timeout() {
+        assert_eq!(
+            "30:9".parse::<Timeout>(),
+            Ok(Timeout::new(
+                Duration::from_secs(30),
+                TimeoutAction::Goto(9)
+            ))
+        );
+        assert_eq!(
+            "5:reprompt".parse::<Timeout>(),
+            Ok(Timeout::new(
+                Duration::from_secs(5),
+                TimeoutAction::Reprompt
+            ))
+        );
+        assert!("5".parse::<Timeout>().is_err());
+        assert!("soon:3".parse::<Timeout>().is_err());
+    }
+}
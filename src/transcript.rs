@@ -0,0 +1,231 @@
+//! # transcript
+//! Attach a [`TranscriptSink`](trait.TranscriptSink.html) with [`Nodes::set_transcript`](../struct.Nodes.html#method.set_transcript)
+//! to receive a timestamped [`Entry`](struct.Entry.html) for every event in a session.
+//! [`JsonLinesSink`](struct.JsonLinesSink.html) writes one JSON object per line:
+//! ```txt
+//! {"at":1571760000000,"event":"node_entered","node":0}
+//! {"at":1571760000000,"event":"prompted","node":0,"text":"What is your name?","options":[]}
+//! {"at":1571760004000,"event":"input","node":0,"raw":"Arthur"}
+//! {"at":1571760004000,"event":"validation","node":0,"accepted":true,"reason":null}
+//! {"at":1571760004000,"event":"variable_set","name":"NAME","value":"Arthur"}
+//! {"at":1571760004000,"event":"transition","from":0,"to":1,"cause":"answer"}
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    io::{self, BufRead, Write},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::NodeId;
+
+/// Why the machine left a node
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Cause {
+    /// The user gave an accepted answer
+    Answer,
+    /// A question ran out of prompts after blank answers, or a branching node had no options left
+    Exhausted,
+    /// The prompt timed out
+    Timeout,
+    /// A question was passed over because its variable was already set
+    Skipped,
+    /// A routing node chose a destination by score
+    Scored,
+    /// A split node chose a variant at random
+    Split,
+    /// A webhook got a successful response, or a command exited with status 0
+    Succeeded,
+    /// A webhook got no successful response, or a command failed
+    Failed,
+}
+
+/// A single thing that happened during a session
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// Execution arrived at a node
+    NodeEntered { node: NodeId },
+    /// A prompt was shown, after template resolution
+    Prompted {
+        node: NodeId,
+        text: String,
+        options: Vec<String>,
+    },
+    /// Raw line entered by the user
+    Input { node: NodeId, raw: String },
+    /// Whether the input was accepted, and why not
+    Validation {
+        node: NodeId,
+        accepted: bool,
+        reason: Option<String>,
+    },
+    /// An answer was stored
+    VariableSet { name: String, value: String },
+    /// The prompt went unanswered too long
+    TimedOut { node: NodeId },
+    /// A webhook got no successful response or a command failed, and why
+    Failed { node: NodeId, reason: String },
+    /// A node named an action for the host application
+    Action { node: NodeId, name: String },
+    /// Execution moved on. `to` is `None` when execution ended
+    Transition {
+        from: NodeId,
+        to: Option<NodeId>,
+        cause: Cause,
+    },
+}
+
+/// Timestamped event
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Entry {
+    /// Milliseconds since the Unix epoch
+    pub at: u64,
+    /// What happened
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+impl Entry {
+    /// Stamp an event with the current time
+    pub fn now(event: Event) -> Self {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        Self { at, event }
+    }
+}
+
+/// Destination for transcript entries
+pub trait TranscriptSink: Send {
+    /// Record one entry
+    fn record(&mut self, entry: &Entry) -> io::Result<()>;
+}
+
+/// Writes each entry as a line of JSON
+#[derive(Debug)]
+pub struct JsonLinesSink<W: Write + Send>(W);
+
+impl<W: Write + Send> JsonLinesSink<W> {
+    /// Construct a fresh sink over a writer
+    pub fn new(writer: W) -> Self {
+        Self(writer)
+    }
+}
+
+impl<W: Write + Send> TranscriptSink for JsonLinesSink<W> {
+    fn record(&mut self, entry: &Entry) -> io::Result<()> {
+        serde_json::to_writer(&mut self.0, entry)?;
+        writeln!(self.0)?;
+        // Flush every line so a crash doesn't lose the end of the session
+        self.0.flush()
+    }
+}
+
+/// Keeps entries in memory, cloning the sink shares the same entries
+#[derive(Clone, Debug, Default)]
+pub struct MemorySink(Arc<Mutex<Vec<Entry>>>);
+
+impl MemorySink {
+    /// Everything recorded so far
+    pub fn entries(&self) -> Vec<Entry> {
+        self.0.lock().expect("Should lock transcript").clone()
+    }
+}
+
+impl TranscriptSink for MemorySink {
+    fn record(&mut self, entry: &Entry) -> io::Result<()> {
+        self.0
+            .lock()
+            .expect("Should lock transcript")
+            .push(entry.clone());
+        Ok(())
+    }
+}
+
+/// Read back a JSON Lines transcript, skipping blank lines
+pub fn read_json_lines<R: BufRead>(reader: R) -> io::Result<Vec<Entry>> {
+    let mut ret = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        ret.push(entry);
+    }
+    Ok(ret)
+}
+
+/// Optional sink held by `Nodes`, so `Nodes` can keep its derives
+#[derive(Default)]
+pub(crate) struct Recorder(pub(crate) Option<Box<dyn TranscriptSink>>);
+
+impl Recorder {
+    /// Stamp and record an event, if anything is listening
+    pub(crate) fn record(&mut self, event: Event) {
+        if let Some(sink) = &mut self.0 {
+            if let Err(e) = sink.record(&Entry::now(event)) {
+                eprintln!("Failed to write transcript: {}", e);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(_) => write!(f, "Recorder(Some(..))"),
+            None => write!(f, "Recorder(None)"),
+        }
+    }
+}
+
+// Where a session is being recorded to doesn't change the session
+impl PartialEq for Recorder {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_json_lines_round_trip() {
+        let entries = vec![
+            Entry {
+                at: 1,
+                event: Event::Input {
+                    node: 0,
+                    raw: "Arthur".into(),
+                },
+            },
+            Entry {
+                at: 2,
+                event: Event::Transition {
+                    from: 0,
+                    to: None,
+                    cause: Cause::Timeout,
+                },
+            },
+        ];
+        let mut sink = JsonLinesSink::new(Vec::new());
+        for entry in &entries {
+            sink.record(entry).unwrap();
+        }
+        let written = String::from_utf8(sink.0).unwrap();
+        assert_eq!(
+            written.lines().next().unwrap(),
+            r#"{"at":1,"event":"input","node":0,"raw":"Arthur"}"#
+        );
+        assert_eq!(read_json_lines(written.as_bytes()).unwrap(), entries);
+    }
+}
@@ -0,0 +1,390 @@
+//! # tui
+//! Runs a flow full screen instead of line by line. Only built with the `tui` feature.
+//!
+//! Branching options are chosen with the arrow keys or their number and confirmed with Enter.
+//! Questions get a text field. A bar along the top shows the nodes visited so far,
+//! and a panel below the prompt shows the answers collected. Esc quits.
+//!
+//! Like [`Nodes::run`](../struct.Nodes.html#method.run), this is just another driver of the step API,
+//! so timeouts and transcripts work the same way.
+
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::{Attribute, Print, SetAttribute},
+    terminal::{self, ClearType},
+};
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    time::Duration,
+};
+
+use crate::{Nodes, Prompt};
+
+/// How often to look at the clock while waiting for a key
+const TICK: Duration = Duration::from_millis(250);
+
+/// One line of the screen
+#[derive(Clone, Debug, PartialEq)]
+struct Line {
+    /// What to show
+    text: String,
+    /// Shown in reverse video
+    highlight: bool,
+}
+
+impl Line {
+    /// Plain line
+    fn plain(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            highlight: false,
+        }
+    }
+
+    /// Highlighted line
+    fn highlighted(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            highlight: true,
+        }
+    }
+}
+
+/// What to do after a key press
+#[derive(Debug, PartialEq)]
+enum Action {
+    /// Keep going
+    Continue,
+    /// Leave, finished or not
+    Quit,
+}
+
+/// Everything on screen that isn't machine state
+#[derive(Debug, Default)]
+struct Screen {
+    /// Highlighted option at a branching node
+    selected: usize,
+    /// Text typed so far at a question node
+    input: String,
+    /// Last error or notice, cleared by the next key
+    message: Option<String>,
+}
+
+impl Screen {
+    /// Hand an answer to the machine and reset the fields for whatever comes next
+    fn submit(&mut self, nodes: &mut Nodes, answer: &str) {
+        let before = nodes.current_node();
+        match nodes.answer(answer) {
+            Ok(_) => self.message = None,
+            Err(e) => self.message = Some(e.to_string()),
+        }
+        if nodes.current_node() != before {
+            self.selected = 0;
+        }
+        self.input.clear();
+    }
+
+    /// React to a key at the current prompt
+    fn key(&mut self, nodes: &mut Nodes, key: KeyEvent) -> Action {
+        if key.code == KeyCode::Esc
+            || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL))
+        {
+            return Action::Quit;
+        }
+        self.message = None;
+        match nodes.current_prompt() {
+            Prompt::Branching { options, .. } => match key.code {
+                KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+                KeyCode::Down => {
+                    self.selected = (self.selected + 1).min(options.len().saturating_sub(1))
+                }
+                KeyCode::Home => self.selected = 0,
+                KeyCode::End => self.selected = options.len().saturating_sub(1),
+                KeyCode::Char(c) => match c.to_digit(10) {
+                    Some(n) if n >= 1 && (n as usize) <= options.len() => {
+                        self.selected = n as usize - 1
+                    }
+                    _ => self.message = Some("Use the arrow keys or an option number".into()),
+                },
+                KeyCode::Enter => {
+                    let answer = (self.selected + 1).to_string();
+                    self.submit(nodes, &answer);
+                }
+                _ => {}
+            },
+            Prompt::Question { .. } => match key.code {
+                KeyCode::Char(c) => self.input.push(c),
+                KeyCode::Backspace => {
+                    self.input.pop();
+                }
+                KeyCode::Enter => {
+                    let answer = self.input.clone();
+                    self.submit(nodes, &answer);
+                }
+                _ => {}
+            },
+            // Any key dismisses the exit message
+            Prompt::Terminating { .. } => self.submit(nodes, ""),
+        }
+        if nodes.is_finished() {
+            Action::Quit
+        } else {
+            Action::Continue
+        }
+    }
+
+    /// Lay out the whole screen for a terminal `width` columns wide
+    fn lines(&self, nodes: &Nodes, width: usize) -> Vec<Line> {
+        let rule = "─".repeat(width);
+        let mut ret = vec![
+            Line::plain(breadcrumbs(nodes.path(), width)),
+            Line::plain(&rule),
+        ];
+        let prompt = nodes.current_prompt();
+        ret.extend(wrap(prompt.text(), width).into_iter().map(Line::plain));
+        ret.push(Line::plain(""));
+        let hint = match &prompt {
+            Prompt::Branching { options, .. } => {
+                for (i, option) in options.iter().enumerate() {
+                    if i == self.selected {
+                        ret.push(Line::highlighted(format!("> {}. {}", i + 1, option)));
+                    } else {
+                        ret.push(Line::plain(format!("  {}. {}", i + 1, option)));
+                    }
+                }
+                "↑/↓ choose · Enter confirm · Esc quit"
+            }
+            Prompt::Question {
+                attempt, attempts, ..
+            } => {
+                ret.push(Line::highlighted(format!("> {}_", self.input)));
+                if *attempt > 0 {
+                    ret.push(Line::plain(format!(
+                        "Attempt {} of {}",
+                        attempt + 1,
+                        attempts
+                    )));
+                }
+                "Type an answer · Enter confirm · Esc quit"
+            }
+            Prompt::Terminating { .. } => "Press any key to exit",
+        };
+        ret.push(Line::plain(""));
+        ret.push(Line::plain(self.message.clone().unwrap_or_default()));
+        if let Some(remaining) = nodes.time_remaining() {
+            ret.push(Line::plain(format!("{}s left", remaining.as_secs())));
+        }
+        ret.push(Line::plain(&rule));
+        ret.push(Line::plain("Answers"));
+        let answers: BTreeMap<&String, String> = nodes
+            .env()
+            .iter()
+            .map(|(name, value)| (name, value.format(nodes.locale())))
+            .collect();
+        let name_width = answers.keys().map(|k| k.len()).max().unwrap_or_default();
+        if answers.is_empty() {
+            ret.push(Line::plain("  (none yet)"));
+        }
+        for (name, value) in answers {
+            ret.push(Line::plain(format!(
+                "  {:width$}  {}",
+                name,
+                value,
+                width = name_width
+            )));
+        }
+        ret.push(Line::plain(&rule));
+        ret.push(Line::plain(hint));
+        ret
+    }
+}
+
+/// Nodes visited, most recent last, trimmed from the front to fit
+fn breadcrumbs(path: &[usize], width: usize) -> String {
+    let mut ret = String::new();
+    for (i, node) in path.iter().enumerate().rev() {
+        let crumb = if i + 1 == path.len() {
+            format!("[{}]", node)
+        } else {
+            format!("{} › ", node)
+        };
+        // Leave room for the label and an ellipsis
+        if ret.chars().count() + crumb.chars().count() + 8 > width {
+            ret.insert_str(0, "… ");
+            break;
+        }
+        ret.insert_str(0, &crumb);
+    }
+    format!("Path: {}", ret)
+}
+
+/// Break text into lines no wider than `width`, at spaces where possible
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut ret = Vec::new();
+    let mut line = String::new();
+    for word in text.split(' ') {
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+            ret.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    ret.push(line);
+    ret
+}
+
+/// Puts the terminal back however `run` exits
+struct Terminal;
+
+impl Terminal {
+    /// Switch to raw mode on the alternate screen
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(Self)
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Draw the screen
+fn draw(out: &mut impl Write, lines: &[Line]) -> io::Result<()> {
+    queue!(out, terminal::Clear(ClearType::All))?;
+    for (row, line) in lines.iter().enumerate() {
+        queue!(out, cursor::MoveTo(0, row as u16))?;
+        if line.highlight {
+            queue!(
+                out,
+                SetAttribute(Attribute::Reverse),
+                Print(&line.text),
+                SetAttribute(Attribute::Reset)
+            )?;
+        } else {
+            queue!(out, Print(&line.text))?;
+        }
+    }
+    out.flush()
+}
+
+/// Run the machine full screen until it finishes or the user quits
+pub fn run(nodes: &mut Nodes) -> io::Result<()> {
+    let _terminal = Terminal::enter()?;
+    let mut stdout = io::stdout();
+    let mut screen = Screen::default();
+    while !nodes.is_finished() {
+        nodes.prompt();
+        let (width, _) = terminal::size()?;
+        draw(&mut stdout, &screen.lines(nodes, width as usize))?;
+        if !event::poll(nodes.time_remaining().map_or(TICK, |t| t.min(TICK)))? {
+            if nodes.check_timeout().is_some() {
+                screen = Screen {
+                    message: Some("Timed out".into()),
+                    ..Default::default()
+                };
+            }
+            continue;
+        }
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Release && screen.key(nodes, key) == Action::Quit {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// Key press without modifiers
+    fn press(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn test_keys() {
+        let mut nodes: Nodes = include_str!("../input.txt").parse().unwrap();
+        let mut screen = Screen::default();
+        screen.key(&mut nodes, press(KeyCode::Enter));
+        for c in "Arthx".chars() {
+            screen.key(&mut nodes, press(KeyCode::Char(c)));
+        }
+        screen.key(&mut nodes, press(KeyCode::Backspace));
+        screen.key(&mut nodes, press(KeyCode::Char('u')));
+        screen.key(&mut nodes, press(KeyCode::Char('r')));
+        assert_eq!(screen.input, "Arthur");
+        screen.key(&mut nodes, press(KeyCode::Enter));
+        assert_eq!(nodes.current_node(), 1);
+        screen.key(&mut nodes, press(KeyCode::Down));
+        screen.key(&mut nodes, press(KeyCode::Down));
+        assert_eq!(screen.selected, 1);
+        screen.key(&mut nodes, press(KeyCode::Char('9')));
+        assert!(screen.message.is_some());
+        screen.key(&mut nodes, press(KeyCode::Char('1')));
+        screen.key(&mut nodes, press(KeyCode::Enter));
+        assert_eq!(nodes.current_node(), 2);
+        assert_eq!(screen.selected, 0);
+        screen.key(&mut nodes, press(KeyCode::Enter));
+        assert_eq!(
+            screen.key(&mut nodes, press(KeyCode::Char('x'))),
+            Action::Quit
+        );
+        assert!(nodes.is_finished());
+        assert_eq!(nodes.path(), &[0, 1, 2, 4]);
+    }
+
+    #[test]
+    fn test_layout() {
+        let mut nodes: Nodes = include_str!("../input.txt").parse().unwrap();
+        nodes.answer("Arthur").unwrap();
+        let screen = Screen::default();
+        let text: Vec<String> = screen
+            .lines(&nodes, 30)
+            .into_iter()
+            .map(|l| {
+                if l.highlight {
+                    format!("*{}", l.text)
+                } else {
+                    l.text
+                }
+            })
+            .collect();
+        let rule = "─".repeat(30);
+        assert_eq!(
+            text,
+            vec![
+                "Path: 0 › [1]",
+                &rule,
+                "Arthur, what is your quest?",
+                "",
+                "*> 1. The Holy Grail",
+                "  2. Run and Hide",
+                "",
+                "",
+                &rule,
+                "Answers",
+                "  NAME  Arthur",
+                &rule,
+                "↑/↓ choose · Enter confirm · Esc quit",
+            ]
+        );
+        assert_eq!(
+            breadcrumbs(&[0, 1, 2, 1, 2, 1, 2], 20),
+            "Path: … 2 › 1 › [2]"
+        );
+        assert_eq!(wrap("one two three", 7), vec!["one two", "three"]);
+    }
+}
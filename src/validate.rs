@@ -0,0 +1,264 @@
+//! # validate
+//! A flow can parse cleanly and still be wrong.
+//! [`validate`](fn.validate.html) looks for destinations that don't exist, nodes nothing leads to,
+//! prompts that can show a variable before it has been set, message keys a locale has no text for,
+//! template filters the flow doesn't have, and exit messages reached without setting a declared output.
+
+use std::{collections::BTreeSet, fmt};
+
+use crate::{
+    analysis::{
+        definitely_set, edges, is_terminating, path_without, possibly_unset, reachable, templates,
+        Edge, UnsetUse,
+    },
+    locale::message_key,
+    template_filters, NodeId, NodeType, Nodes,
+};
+
+/// Something wrong with a flow
+#[derive(Clone, Debug, PartialEq)]
+pub enum Problem {
+    /// The flow has no nodes at all
+    Empty,
+    /// An edge leads to a node that doesn't exist
+    DanglingDestination(Edge),
+    /// No path from the start leads here
+    Unreachable(NodeId),
+    /// A prompt can show a variable nothing has set yet
+    PossiblyUnset(UnsetUse),
+    /// A message key the flow uses has no text in a locale
+    MissingTranslation {
+        /// Locale lacking the text
+        locale: String,
+        /// Key, without the `#`
+        key: String,
+    },
+    /// A template uses a filter the flow doesn't have
+    UnknownFilter {
+        /// Node whose prompt uses it
+        node: NodeId,
+        /// Filter name
+        filter: String,
+    },
+    /// An exit message can be reached without setting an output the flow declares
+    UnsetOutput {
+        /// Terminating node
+        node: NodeId,
+        /// Output name
+        variable: String,
+        /// A shortest path from the start to `node` that never sets `variable`
+        path: Vec<NodeId>,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Empty => write!(f, "flow has no nodes"),
+            Problem::DanglingDestination(edge) => {
+                write!(f, "{} leads to a node that doesn't exist", edge)
+            }
+            Problem::Unreachable(node) => write!(f, "node {} can never be reached", node),
+            Problem::PossiblyUnset(u) => write!(f, "{}", u),
+            Problem::MissingTranslation { locale, key } => {
+                write!(f, "#{} has no text in the {} catalogue", key, locale)
+            }
+            Problem::UnknownFilter { node, filter } => {
+                write!(f, "node {} uses unknown filter {}", node, filter)
+            }
+            Problem::UnsetOutput {
+                node,
+                variable,
+                path,
+            } => {
+                let path: Vec<String> = path.iter().map(|n| n.to_string()).collect();
+                write!(
+                    f,
+                    "node {} can end the flow without setting output ${}, e.g. via {}",
+                    node,
+                    variable,
+                    path.join(" -> ")
+                )
+            }
+        }
+    }
+}
+
+/// Every problem found in a flow, in a stable order
+pub fn validate(nodes: &Nodes) -> Vec<Problem> {
+    let mut ret = Vec::new();
+    if nodes.flow.nodes.is_empty() {
+        ret.push(Problem::Empty);
+        return ret;
+    }
+    for edge in edges(nodes) {
+        if edge.to >= nodes.flow.nodes.len() {
+            ret.push(Problem::DanglingDestination(edge));
+        }
+    }
+    let starts = nodes.flow.metadata.starts();
+    let live: BTreeSet<NodeId> = starts.iter().flat_map(|s| reachable(nodes, *s)).collect();
+    for node in 0..nodes.flow.nodes.len() {
+        if !live.contains(&node) {
+            ret.push(Problem::Unreachable(node));
+        }
+    }
+    for start in &starts {
+        for unset in possibly_unset(nodes, *start) {
+            let found = Problem::PossiblyUnset(unset);
+            if !ret.contains(&found) {
+                ret.push(found);
+            }
+        }
+    }
+    let catalogues = &nodes.flow.catalogues;
+    let keys = message_keys(nodes);
+    for locale in catalogues.locales() {
+        for key in &keys {
+            if catalogues.get(locale).and_then(|c| c.get(key)).is_none() {
+                ret.push(Problem::MissingTranslation {
+                    locale: locale.into(),
+                    key: key.clone(),
+                });
+            }
+        }
+    }
+    for node in 0..nodes.flow.nodes.len() {
+        let mut unknown: Vec<String> = Vec::new();
+        for template in templates(nodes, node) {
+            for filter in template_filters(template) {
+                if !nodes.flow.filters.contains(&filter) && !unknown.contains(&filter) {
+                    unknown.push(filter);
+                }
+            }
+        }
+        ret.extend(
+            unknown
+                .into_iter()
+                .map(|filter| Problem::UnknownFilter { node, filter }),
+        );
+    }
+    // Report each unset output at each exit message once, from the first start reaching it
+    let mut unset_outputs: Vec<(NodeId, &String)> = Vec::new();
+    for start in &starts {
+        for (node, set) in definitely_set(nodes, *start) {
+            if !is_terminating(nodes, node) {
+                continue;
+            }
+            for variable in &nodes.flow.metadata.outputs {
+                if !set.contains(variable) && !unset_outputs.contains(&(node, variable)) {
+                    unset_outputs.push((node, variable));
+                    ret.push(Problem::UnsetOutput {
+                        node,
+                        variable: variable.clone(),
+                        path: path_without(nodes, *start, node, variable),
+                    });
+                }
+            }
+        }
+    }
+    ret
+}
+
+/// Every message key used, in the order they first appear
+fn message_keys(nodes: &Nodes) -> Vec<String> {
+    let mut ret: Vec<String> = Vec::new();
+    for node in &nodes.flow.nodes {
+        let texts: Vec<&String> = match &node.node_type {
+            NodeType::Branching(question, options) => std::iter::once(question)
+                .chain(options.iter().map(|o| &o.text))
+                .collect(),
+            NodeType::Question(_, _, qs) => qs.iter().collect(),
+            NodeType::Terminating(message) => vec![message],
+            NodeType::Routing(_)
+            | NodeType::Split(_)
+            | NodeType::Webhook(_)
+            | NodeType::Command(_) => Vec::new(),
+        };
+        for key in texts.into_iter().filter_map(|t| message_key(t)) {
+            if !ret.iter().any(|k| k == key) {
+                ret.push(key.into());
+            }
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{analysis::EdgeKind, Flow};
+    use pretty_assertions::assert_eq;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_validate() {
+        for flow in &["input.txt", "AdReadiness.txt"] {
+            assert_eq!(validate(&Nodes::from_file(flow).unwrap()), vec![]);
+        }
+        let broken: Nodes = "2\nQUEST\n$NAME?\nGo:3\n3\nNever\n".parse().unwrap();
+        assert_eq!(
+            validate(&broken),
+            vec![
+                Problem::DanglingDestination(Edge {
+                    from: 0,
+                    kind: EdgeKind::Option(1),
+                    to: 3,
+                }),
+                Problem::Unreachable(1),
+                Problem::PossiblyUnset(UnsetUse {
+                    node: 0,
+                    variable: "NAME".into(),
+                    path: vec![0],
+                }),
+            ]
+        );
+        let mut flow: Flow = "3\n#bye\n".parse().unwrap();
+        flow.catalogues_mut()
+            .add("es", "adios = Adiós".parse().unwrap());
+        assert_eq!(
+            validate(&Nodes::from_flow(Arc::new(flow)))
+                .into_iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "#bye has no text in the en catalogue",
+                "#bye has no text in the es catalogue",
+            ]
+        );
+        let mut flow: Flow = "3\nBye ${NAME|default:you|shout|upper|whisper}\n"
+            .parse()
+            .unwrap();
+        flow.filters_mut().register("shout", |text, _| text.into());
+        assert_eq!(
+            validate(&Nodes::from_flow(Arc::new(flow))),
+            vec![Problem::UnknownFilter {
+                node: 0,
+                filter: "whisper".into()
+            }]
+        );
+        let outputs: Nodes = "@start 1\n@outputs NAME COLOR\n3\nUnused\n1\n2\n4\nNAME\nWhat is your name?\n/* */\n2\nCOLOR\nFavorite color?\nBlue:3\n3\n$NAME likes $COLOR\n3\nGoodbye\n"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            validate(&outputs),
+            vec![
+                Problem::Unreachable(0),
+                Problem::UnsetOutput {
+                    node: 4,
+                    variable: "NAME".into(),
+                    path: vec![1, 4],
+                },
+                Problem::UnsetOutput {
+                    node: 4,
+                    variable: "COLOR".into(),
+                    path: vec![1, 4],
+                },
+            ]
+        );
+        assert_eq!(
+            validate(&outputs)[1].to_string(),
+            "node 4 can end the flow without setting output $NAME, e.g. via 1 -> 4"
+        );
+    }
+}
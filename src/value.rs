@@ -0,0 +1,238 @@
+//! # value
+//! Variables hold a [`Value`](enum.Value.html): text, a whole number, a decimal, a yes/no flag or a list.
+//! Answers typed at a question are always text, but variables set from JSON or by the library keep their type.
+//!
+//! In templates, lists are joined with commas and numbers are grouped the way the session's locale writes them:
+//! ```
+//! # use winnow_sm::Value;
+//! assert_eq!(Value::from(1234567.5).format("en"), "1,234,567.5");
+//! assert_eq!(Value::from(1234567.5).format("es"), "1.234.567,5");
+//! assert_eq!(Value::from(vec![Value::from("Google"), Value::from("Facebook")]).format("en"), "Google, Facebook");
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A variable's value
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Value {
+    /// Yes or no
+    Bool(bool),
+    /// Whole number
+    Int(i64),
+    /// Decimal number
+    Float(f64),
+    /// Text
+    String(String),
+    /// Several values, like the choices of a multi-select
+    List(Vec<Value>),
+}
+
+impl Value {
+    /// As a whole number, if it is one or is text or a decimal that reads as one
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(n) => Some(*n),
+            Value::Float(f) if f.fract() == 0.0 => Some(*f as i64),
+            Value::String(s) => s.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// As a number, if it is one or is text that reads as one
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Value::Int(n) => Some(*n as f64),
+            Value::Float(f) => Some(*f),
+            Value::String(s) => s.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// As a flag. Text reads as one if it is `yes`/`no`, `y`/`n`, `true`/`false` or `1`/`0`, in any case
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            Value::Int(1) => Some(true),
+            Value::Int(0) => Some(false),
+            Value::String(s) => match s.trim().to_lowercase().as_str() {
+                "yes" | "y" | "true" | "1" => Some(true),
+                "no" | "n" | "false" | "0" => Some(false),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// As a list. Anything that isn't one is a list of just itself
+    pub fn as_list(&self) -> Vec<Value> {
+        match self {
+            Value::List(items) => items.clone(),
+            other => vec![other.clone()],
+        }
+    }
+
+    /// How the value reads in a prompt shown in `locale`
+    pub fn format(&self, locale: &str) -> String {
+        match self {
+            Value::Int(n) => group_number(&n.to_string(), locale),
+            Value::Float(f) => group_number(&f.to_string(), locale),
+            Value::List(items) => items
+                .iter()
+                .map(|v| v.format(locale))
+                .collect::<Vec<_>>()
+                .join(", "),
+            other => other.to_string(),
+        }
+    }
+}
+
+/// Thousands separator and decimal mark for a locale, going by its language
+fn separators(locale: &str) -> (&'static str, &'static str) {
+    let language = locale
+        .split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    match language.as_str() {
+        "de" | "es" | "id" | "it" | "nl" | "pt" | "tr" | "da" => (".", ","),
+        "fr" | "cs" | "fi" | "nb" | "pl" | "ru" | "sv" | "uk" => (" ", ","),
+        _ => (",", "."),
+    }
+}
+
+/// Rewrite a plain number like `-1234.5` with a locale's separators
+fn group_number(plain: &str, locale: &str) -> String {
+    let (group, decimal) = separators(locale);
+    let (sign, digits) = match plain.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", plain),
+    };
+    let (whole, fraction) = match digits.split_once('.') {
+        Some((w, f)) => (w, Some(f)),
+        None => (digits, None),
+    };
+    let mut ret = String::from(sign);
+    for (i, c) in whole.chars().enumerate() {
+        if i > 0 && (whole.len() - i) % 3 == 0 {
+            ret.push_str(group);
+        }
+        ret.push(c);
+    }
+    if let Some(fraction) = fraction {
+        ret.push_str(decimal);
+        ret.push_str(fraction);
+    }
+    ret
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Float(n) => write!(f, "{}", n),
+            Value::String(s) => write!(f, "{}", s),
+            Value::List(items) => {
+                let items: Vec<String> = items.iter().map(|v| v.to_string()).collect();
+                write!(f, "{}", items.join(", "))
+            }
+        }
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.into())
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Value::Int(n)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Float(n)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(items: Vec<Value>) -> Self {
+        Value::List(items)
+    }
+}
+
+impl From<serde_json::Value> for Value {
+    /// Objects and `null` have no equivalent, so are kept as their JSON text
+    fn from(json: serde_json::Value) -> Self {
+        use serde_json::Value as Json;
+        match json {
+            Json::Bool(b) => Value::Bool(b),
+            Json::Number(n) => match n.as_i64() {
+                Some(i) => Value::Int(i),
+                None => Value::Float(n.as_f64().unwrap_or_default()),
+            },
+            Json::String(s) => Value::String(s),
+            Json::Array(items) => Value::List(items.into_iter().map(Value::from).collect()),
+            other => Value::String(other.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(Value::from(" 42 ").as_int(), Some(42));
+        assert_eq!(Value::from(42.0).as_int(), Some(42));
+        assert_eq!(Value::from(42.5).as_int(), None);
+        assert_eq!(Value::from("Yes").as_bool(), Some(true));
+        assert_eq!(Value::from("maybe").as_bool(), None);
+        assert_eq!(Value::from(7).as_list(), vec![Value::Int(7)]);
+        let json: serde_json::Value =
+            serde_json::from_str(r#"[1, 2.5, true, "x", {"a": null}]"#).unwrap();
+        assert_eq!(
+            Value::from(json),
+            Value::List(vec![
+                Value::Int(1),
+                Value::Float(2.5),
+                Value::Bool(true),
+                Value::String("x".into()),
+                Value::String(r#"{"a":null}"#.into()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(Value::from(-1234567).format("en"), "-1,234,567");
+        assert_eq!(Value::from(999).format("en"), "999");
+        assert_eq!(Value::from(1234.25).format("fr-CA"), "1 234,25");
+        assert_eq!(Value::from(1234).to_string(), "1234");
+        assert_eq!(Value::from(true).format("es"), "true");
+        // Round trips through JSON keep their type
+        let list = Value::from(vec![Value::from(1), Value::from("two")]);
+        let json = serde_json::to_string(&list).unwrap();
+        assert_eq!(json, r#"[1,"two"]"#);
+        assert_eq!(serde_json::from_str::<Value>(&json).unwrap(), list);
+    }
+}
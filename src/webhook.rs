@@ -0,0 +1,421 @@
+//! # webhook
+//! A webhook node asks nothing. It POSTs the session's variables as a JSON object to a URL,
+//! then moves on to one node if the response is a success and another if not:
+//! ```txt
+//! 6 // Node type must be 6
+//! https://crm.example.com/leads // URL to post to
+//! 9 // Node to go to on a 2xx response
+//! 23 // Node to go to otherwise, or if there was no response
+//! send NAME N0 // Variables to post, every one if left out
+//! receive TICKET = ticket.id // Set $TICKET from a field of the JSON response
+//! retries 2 // Try again this many times after no response or a 5xx, 0 if left out
+//! timeout 5 // Seconds to wait for each attempt, 10 if left out
+//! ```
+//! Each setting line is optional. A `receive` field the response doesn't have leaves its variable alone,
+//! and a response that isn't JSON counts as a failure if there are any `receive` lines.
+//!
+//! Posts are made through a [`Transport`](trait.Transport.html), [`HttpTransport`](struct.HttpTransport.html)
+//! unless [`Nodes::set_webhook_transport`](../struct.Nodes.html#method.set_webhook_transport) says otherwise.
+//! Scenarios, replays and simulations use a [`StubTransport`](struct.StubTransport.html), so they never post anything.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    transcript::{Cause, Entry, Event},
+    Assignments, Env, NodeId, Value,
+};
+
+/// Seconds to wait for a response unless the node says otherwise
+const DEFAULT_TIMEOUT: u64 = 10;
+
+/// Where to post and what to do with the answer
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Webhook {
+    /// URL to post to
+    pub(crate) url: String,
+    /// Node to go to on success
+    pub(crate) success: NodeId,
+    /// Node to go to on failure
+    pub(crate) failure: NodeId,
+    /// Variables to post, or every one if empty
+    pub(crate) send: Vec<String>,
+    /// Variables to set, and the dotted path of the response field to set each from
+    pub(crate) receive: Vec<(String, String)>,
+    /// Further attempts after no response or a server error
+    pub(crate) retries: u32,
+    /// How long to wait for each attempt
+    pub(crate) timeout: Duration,
+}
+
+impl Webhook {
+    /// Construct a webhook with no settings
+    pub(crate) fn new(url: &str, success: NodeId, failure: NodeId) -> Self {
+        Self {
+            url: url.into(),
+            success,
+            failure,
+            send: Vec::new(),
+            receive: Vec::new(),
+            retries: 0,
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT),
+        }
+    }
+
+    /// The JSON object to post
+    pub(crate) fn body(&self, env: &Env) -> serde_json::Value {
+        let body: serde_json::Map<String, serde_json::Value> = env
+            .iter()
+            .filter(|(name, _)| self.send.is_empty() || self.send.contains(name))
+            .map(|(name, value)| {
+                let value = serde_json::to_value(value).expect("Should serialize value");
+                (name.clone(), value)
+            })
+            .collect();
+        serde_json::Value::Object(body)
+    }
+
+    /// What to post for `node`, given the session's variables
+    pub(crate) fn request(&self, node: NodeId, env: &Env) -> Request {
+        Request {
+            node,
+            url: self.url.clone(),
+            body: self.body(env),
+            receive: self.receive.clone(),
+            retries: self.retries,
+            timeout: self.timeout,
+        }
+    }
+}
+
+/// A post a webhook node wants made
+#[derive(Clone, Debug, PartialEq)]
+pub struct Request {
+    /// Webhook node making it
+    pub node: NodeId,
+    /// URL to post to
+    pub url: String,
+    /// JSON object to post
+    pub body: serde_json::Value,
+    /// Variables to set, and the dotted path of the response field to set each from
+    pub receive: Vec<(String, String)>,
+    /// Further attempts after no response or a server error
+    pub retries: u32,
+    /// How long to wait for each attempt
+    pub timeout: Duration,
+}
+
+/// Carries out webhook posts
+pub trait Transport: fmt::Debug + Send + Sync {
+    /// Make the post, returning the variables to set from the response.
+    /// An error says why the call counts as a failure.
+    fn send(&self, request: &Request) -> Result<Vec<(String, Value)>, String>;
+}
+
+/// Posts over HTTP, the default
+#[derive(Debug, Default)]
+pub struct HttpTransport;
+
+impl Transport for HttpTransport {
+    fn send(&self, request: &Request) -> Result<Assignments, String> {
+        let agent = ureq::AgentBuilder::new().timeout(request.timeout).build();
+        let mut attempts = 0;
+        let response = loop {
+            match agent.post(&request.url).send_json(&request.body) {
+                Ok(response) => break response,
+                Err(e) => {
+                    let retry = match &e {
+                        ureq::Error::Status(status, _) => *status >= 500,
+                        ureq::Error::Transport(_) => true,
+                    };
+                    if !retry || attempts >= request.retries {
+                        return Err(e.to_string());
+                    }
+                    attempts += 1;
+                }
+            }
+        };
+        if request.receive.is_empty() {
+            return Ok(Vec::new());
+        }
+        let json: serde_json::Value = response
+            .into_json()
+            .map_err(|e| format!("{}: response isn't JSON: {}", request.url, e))?;
+        Ok(request
+            .receive
+            .iter()
+            .filter_map(|(name, path)| {
+                Some((name.clone(), Value::from(field(&json, path)?.clone())))
+            })
+            .collect())
+    }
+}
+
+/// Posts nothing. Each node gets the outcomes pushed for it in turn,
+/// and once those run out succeeds without receiving anything. Keeps every request it's given
+/// # Example
+///
+/// ```
+/// # use winnow_sm::{webhook::StubTransport, Nodes, Step};
+/// # use std::sync::Arc;
+/// let flow = "1\n1\n1\nNAME\nWhat is your name?\n/* */\n6\nhttps://example.com/hook\n2\n3\n3\nSent\n3\nNot sent\n";
+/// let mut nodes: Nodes = flow.parse().unwrap();
+/// let transport = StubTransport::default();
+/// transport.push(1, Err("no thanks".into()));
+/// nodes.set_webhook_transport(Arc::new(transport));
+/// assert_eq!(nodes.answer("Arthur"), Ok(Step::Transition(3)));
+/// ```
+#[derive(Debug, Default)]
+pub struct StubTransport {
+    /// Outcomes still to give, by node
+    outcomes: Mutex<HashMap<NodeId, VecDeque<Result<Assignments, String>>>>,
+    /// Requests given so far
+    requests: Mutex<Vec<Request>>,
+}
+
+impl StubTransport {
+    /// Queue up the next outcome for `node`
+    pub fn push(&self, node: NodeId, outcome: Result<Vec<(String, Value)>, String>) {
+        let mut outcomes = self.outcomes.lock().expect("Should lock outcomes");
+        outcomes.entry(node).or_default().push_back(outcome);
+    }
+
+    /// Every request made so far, in order
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().expect("Should lock requests").clone()
+    }
+
+    /// Give each node the outcomes it had in a recorded transcript: succeeding with the variables
+    /// set along the way, or failing for the reason recorded
+    pub fn from_recording(recording: &[Entry]) -> Self {
+        let ret = Self::default();
+        let mut received = Vec::new();
+        let mut failure = None;
+        for entry in recording {
+            match &entry.event {
+                Event::VariableSet { name, value } => {
+                    received.push((name.clone(), value.as_str().into()))
+                }
+                Event::Failed { reason, .. } => failure = Some(reason.clone()),
+                Event::Transition { from, cause, .. } => {
+                    match cause {
+                        Cause::Succeeded => ret.push(*from, Ok(received.clone())),
+                        Cause::Failed => ret.push(
+                            *from,
+                            Err(failure
+                                .clone()
+                                .unwrap_or_else(|| "failed when recorded".into())),
+                        ),
+                        _ => {}
+                    }
+                    received.clear();
+                    failure = None;
+                }
+                _ => {}
+            }
+        }
+        ret
+    }
+}
+
+impl Transport for StubTransport {
+    fn send(&self, request: &Request) -> Result<Assignments, String> {
+        let mut requests = self.requests.lock().expect("Should lock requests");
+        requests.push(request.clone());
+        let mut outcomes = self.outcomes.lock().expect("Should lock outcomes");
+        outcomes
+            .get_mut(&request.node)
+            .and_then(VecDeque::pop_front)
+            .unwrap_or_else(|| Ok(Vec::new()))
+    }
+}
+
+/// Shared handle to a transport, so `Nodes` can keep its derives
+#[derive(Clone, Debug)]
+pub(crate) struct SharedTransport(pub(crate) Arc<dyn Transport>);
+
+impl Default for SharedTransport {
+    fn default() -> Self {
+        Self(Arc::new(HttpTransport))
+    }
+}
+
+// Two machines don't differ just because they post differently
+impl PartialEq for SharedTransport {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+/// The field at a dotted path like `ticket.id` or `items.0.name`, if there is one
+fn field<'a>(json: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
+    path.split('.').try_fold(json, |json, key| match json {
+        serde_json::Value::Array(items) => items.get(key.parse::<usize>().ok()?),
+        other => other.get(key),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{transcript::Cause, Nodes, Step};
+    use pretty_assertions::assert_eq;
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    /// Serve the given responses in turn on a local port, keeping the bodies posted
+    fn stub(responses: Vec<(u16, &'static str)>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", server.server_addr());
+        let posted = Arc::new(Mutex::new(Vec::new()));
+        let received = posted.clone();
+        thread::spawn(move || {
+            for (status, body) in responses {
+                let mut request = server.recv().unwrap();
+                let mut content = String::new();
+                request.as_reader().read_to_string(&mut content).unwrap();
+                received.lock().unwrap().push(content);
+                let response = tiny_http::Response::from_string(body).with_status_code(status);
+                request.respond(response).unwrap();
+            }
+        });
+        (url, posted)
+    }
+
+    /// A flow asking a name, then posting it
+    fn flow(url: &str, settings: &str) -> Nodes {
+        format!(
+            "1\n1\n1\nNAME\nWhat is your name?\n/* */\n6\n{}\n2\n3\n{}3\nThanks $NAME, ticket $TICKET\n3\nSomething went wrong\n",
+            url, settings
+        )
+        .parse()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_field() {
+        let json: serde_json::Value =
+            serde_json::from_str(r#"{"ticket": {"id": 7}, "items": [{"name": "a"}]}"#).unwrap();
+        assert_eq!(field(&json, "ticket.id"), Some(&serde_json::json!(7)));
+        assert_eq!(field(&json, "items.0.name"), Some(&serde_json::json!("a")));
+        assert_eq!(field(&json, "items.x"), None);
+    }
+
+    #[test]
+    fn test_webhook_success() {
+        let (url, posted) = stub(vec![(503, ""), (200, r#"{"ticket": {"id": 42}}"#)]);
+        let mut env = Env::default();
+        env.set_variable("SECRET", "hunter2".into());
+        let mut nodes = flow(
+            &url,
+            "send NAME\nreceive TICKET = ticket.id\nretries 1\ntimeout 5\n",
+        )
+        .with_env(env);
+        assert_eq!(nodes.answer("Arthur"), Ok(Step::Transition(2)));
+        assert_eq!(nodes.prompt().text(), "Thanks Arthur, ticket 42");
+        assert_eq!(nodes.env().get_int("TICKET"), Some(42));
+        assert_eq!(
+            posted.lock().unwrap().clone(),
+            vec![r#"{"NAME":"Arthur"}"#, r#"{"NAME":"Arthur"}"#]
+        );
+    }
+
+    #[test]
+    fn test_webhook_failure() {
+        // Client errors aren't retried
+        let (url, posted) = stub(vec![(404, "")]);
+        let mut nodes = flow(&url, "retries 3\n");
+        assert_eq!(nodes.answer("Robin"), Ok(Step::Transition(3)));
+        assert_eq!(posted.lock().unwrap().len(), 1);
+        // Nobody listening
+        let mut nodes = flow("http://127.0.0.1:9/hook", "");
+        assert_eq!(nodes.answer("Robin"), Ok(Step::Transition(3)));
+        // A response that can't be read
+        let (url, _) = stub(vec![(200, "not json")]);
+        let mut nodes = flow(&url, "receive TICKET = id\n");
+        let sink = crate::transcript::MemorySink::default();
+        nodes.set_transcript(Some(Box::new(sink.clone())));
+        nodes.answer("Robin").unwrap();
+        assert_eq!(nodes.current_node(), 3);
+        assert!(sink.entries().iter().any(|e| matches!(
+            e.event,
+            crate::transcript::Event::Transition {
+                from: 1,
+                cause: Cause::Failed,
+                ..
+            }
+        )));
+    }
+
+    #[test]
+    fn test_stub_transport() {
+        // Recorded against a live server, replayed with nobody listening
+        let (url, _) = stub(vec![(200, r#"{"ticket": {"id": 42}}"#), (500, "")]);
+        for _ in 0..2 {
+            let sink = crate::transcript::MemorySink::default();
+            let mut nodes = flow(&url, "receive TICKET = ticket.id\n");
+            nodes.set_transcript(Some(Box::new(sink.clone())));
+            nodes.prompt();
+            nodes.answer("Arthur").unwrap();
+            nodes.prompt();
+            let gone = flow("http://127.0.0.1:9/hook", "receive TICKET = ticket.id\n");
+            assert_eq!(crate::replay::replay(gone, &sink.entries()), Ok(()));
+        }
+        // Scenarios take the success node without posting
+        let scenario =
+            &crate::scenario::parse_scenarios("scenario: Offline\n> Arthur\n>\nends: 2\n").unwrap()
+                [0];
+        assert_eq!(scenario.run(flow("http://127.0.0.1:9/hook", "")), vec![]);
+        // Outcomes pushed for a node are used up in turn
+        let transport = StubTransport::default();
+        transport.push(1, Ok(vec![("TICKET".into(), 7.into())]));
+        let mut nodes = flow("http://127.0.0.1:9/hook", "");
+        nodes.set_webhook_transport(Arc::new(transport));
+        assert_eq!(nodes.answer("Robin"), Ok(Step::Transition(2)));
+        assert_eq!(nodes.prompt().text(), "Thanks Robin, ticket 7");
+    }
+
+    #[test]
+    fn test_vetoed_webhook() {
+        use crate::hooks::{Decision, Hooks};
+
+        /// Won't let anything leave the webhook node
+        struct Stay;
+
+        impl Hooks for Stay {
+            fn on_transition(
+                &mut self,
+                from: NodeId,
+                _to: Option<NodeId>,
+                _cause: Cause,
+                env: &Env,
+            ) -> Decision {
+                match from {
+                    1 => {
+                        // Told what the call would set
+                        assert_eq!(env.get_int("TICKET"), Some(7));
+                        Decision::Veto
+                    }
+                    _ => Decision::Allow,
+                }
+            }
+        }
+
+        let transport = Arc::new(StubTransport::default());
+        transport.push(1, Ok(vec![("TICKET".into(), 7.into())]));
+        let mut nodes = flow("http://127.0.0.1:9/hook", "");
+        nodes.set_webhook_transport(transport.clone());
+        nodes.set_hooks(Some(Box::new(Stay)));
+        assert_eq!(nodes.answer("Arthur"), Ok(Step::Reprompt));
+        assert_eq!(nodes.current_node(), 1);
+        // Posted once, and nothing received is kept
+        assert_eq!(transport.requests().len(), 1);
+        assert_eq!(nodes.env().get("TICKET"), None);
+    }
+}